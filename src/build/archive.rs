@@ -0,0 +1,509 @@
+use std::io::{self, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::resources::conf::{MAX_ARCHIVE_ENTRIES, MAX_EXTRACTED_SIZE};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Filesystem error: {0}")]
+    Filesystem(io::Error),
+    #[error("Zip archive error: {0}")]
+    Zip(zip::result::ZipError),
+    #[error("Unsafe path in archive: {0}")]
+    UnsafePath(PathBuf),
+    #[error("Link in archive points outside of it: \"{0}\" -> \"{1}\"")]
+    UnsafeLink(PathBuf, PathBuf),
+    #[error("Archive has more than {0} entries")]
+    TooManyEntries(usize),
+    #[error("Archive is larger than {0} bytes when extracted")]
+    TooLarge(u64),
+}
+
+/// Limits on extracted contents, protecting against archive bombs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub max_entries: usize,
+    pub max_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_entries: MAX_ARCHIVE_ENTRIES,
+            max_size: MAX_EXTRACTED_SIZE,
+        }
+    }
+}
+
+/// Entries and bytes extracted so far
+struct Budget {
+    limits: Limits,
+    entries: usize,
+    size: u64,
+}
+
+impl Budget {
+    fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            entries: 0,
+            size: 0,
+        }
+    }
+    fn entry(&mut self) -> Result<(), ArchiveError> {
+        self.entries += 1;
+
+        if self.entries > self.limits.max_entries {
+            return Err(ArchiveError::TooManyEntries(self.limits.max_entries));
+        }
+
+        Ok(())
+    }
+    fn size(&mut self, size: u64) -> Result<(), ArchiveError> {
+        self.size = self.size.saturating_add(size);
+
+        if self.size > self.limits.max_size {
+            return Err(ArchiveError::TooLarge(self.limits.max_size));
+        }
+
+        Ok(())
+    }
+    fn remaining(&self) -> u64 {
+        self.limits.max_size.saturating_sub(self.size)
+    }
+}
+
+/// Normalizes a path from an archive, returning `None` if it's absolute or
+/// contains `..`
+///
+/// Paths only referring to the root (e.g. `./`) normalize to an empty path.
+pub fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => normalized.push(p),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+/// Whether a link in `dir` (relative to the root) pointing to `target`
+/// stays inside the root
+fn link_stays_inside(dir: &Path, target: &Path) -> bool {
+    let mut depth = dir.components().count();
+
+    for c in target.components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Returns the real path of `path` relative to the real path of `root`, or
+/// `None` if it's outside of `root`
+fn real_relative(root: &Path, path: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let path = path.canonicalize().ok()?;
+
+    path.strip_prefix(root).ok().map(Path::to_path_buf)
+}
+
+/// Checks a link about to be created at `link` (relative to `dest`)
+fn check_link(dest: &Path, link: &Path, target: &Path) -> Result<(), ArchiveError> {
+    let unsafe_link = || ArchiveError::UnsafeLink(link.to_path_buf(), target.to_path_buf());
+
+    let parent = dest.join(link.parent().unwrap_or(Path::new("")));
+
+    // Directories may themselves be links, so use the real location when
+    // it's already been extracted
+    let dir = if parent.exists() {
+        real_relative(dest, &parent).ok_or_else(unsafe_link)?
+    } else {
+        link.parent().map(Path::to_path_buf).unwrap_or_default()
+    };
+
+    if link_stays_inside(&dir, target) {
+        Ok(())
+    } else {
+        Err(unsafe_link())
+    }
+}
+
+/// Checks that every link in an extracted directory stays inside it
+///
+/// Links are checked individually during extraction, but links to other
+/// links can only be resolved once everything is extracted.
+fn verify_links(dest: &Path) -> Result<(), ArchiveError> {
+    for e in WalkDir::new(dest).follow_links(false) {
+        let e = e.map_err(|e| ArchiveError::Filesystem(e.into()))?;
+
+        if !e.path_is_symlink() {
+            continue;
+        }
+
+        let rel = e
+            .path()
+            .strip_prefix(dest)
+            .unwrap_or(e.path())
+            .to_path_buf();
+
+        match e.path().canonicalize() {
+            Ok(_) => {
+                if real_relative(dest, e.path()).is_none() {
+                    let target = std::fs::read_link(e.path()).unwrap_or_default();
+                    return Err(ArchiveError::UnsafeLink(rel, target));
+                }
+            }
+            // Dangling links can only be checked by their text
+            Err(_) => {
+                let target = std::fs::read_link(e.path()).map_err(ArchiveError::Filesystem)?;
+                check_link(dest, &rel, &target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpacks a tar archive from a (possibly decompressing) reader into `dest`
+pub fn unpack_tar<R: Read>(reader: R, dest: &Path, limits: Limits) -> Result<(), ArchiveError> {
+    let mut archive = tar::Archive::new(reader);
+    let mut budget = Budget::new(limits);
+
+    std::fs::create_dir_all(dest).map_err(ArchiveError::Filesystem)?;
+
+    for entry in archive.entries().map_err(ArchiveError::Filesystem)? {
+        let mut entry = entry.map_err(ArchiveError::Filesystem)?;
+        let path = entry.path().map_err(ArchiveError::Filesystem)?.into_owned();
+
+        let rel = relative_path(&path).ok_or_else(|| ArchiveError::UnsafePath(path.clone()))?;
+
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+
+        budget.entry()?;
+        budget.size(entry.size())?;
+
+        match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::Directory => {}
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()
+                    .map_err(ArchiveError::Filesystem)?
+                    .ok_or_else(|| ArchiveError::UnsafePath(path.clone()))?
+                    .into_owned();
+
+                check_link(dest, &rel, &target)?;
+            }
+            tar::EntryType::Link => {
+                let target = entry
+                    .link_name()
+                    .map_err(ArchiveError::Filesystem)?
+                    .ok_or_else(|| ArchiveError::UnsafePath(path.clone()))?
+                    .into_owned();
+
+                // Hard links are relative to the archive's root
+                if relative_path(&target).is_none() {
+                    return Err(ArchiveError::UnsafeLink(rel, target));
+                }
+            }
+            t => {
+                warn!(
+                    "Skipping unsupported archive entry \"{}\" ({:?})",
+                    rel.display(),
+                    t
+                );
+                continue;
+            }
+        }
+
+        // Also refuses to write through links leading outside of `dest`
+        if !entry.unpack_in(dest).map_err(ArchiveError::Filesystem)? {
+            return Err(ArchiveError::UnsafePath(path));
+        }
+    }
+
+    debug!(
+        "Extracted {} entries ({} bytes) to \"{}\"",
+        budget.entries,
+        budget.size,
+        dest.display()
+    );
+
+    verify_links(dest)
+}
+
+/// Unpacks a zip archive into `dest`
+pub fn unpack_zip<R: Read + Seek>(
+    reader: R,
+    dest: &Path,
+    limits: Limits,
+) -> Result<(), ArchiveError> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(ArchiveError::Zip)?;
+
+    unpack_zip_entries(&mut archive, dest, limits, |p| Some(p.to_path_buf()))
+}
+
+/// Unpacks entries of an opened zip archive into `dest`
+///
+/// `map` returns the path of an entry relative to `dest`, or `None` to skip
+/// it. Skipped entries don't count towards the limits.
+pub fn unpack_zip_entries<R, F>(
+    archive: &mut zip::ZipArchive<R>,
+    dest: &Path,
+    limits: Limits,
+    mut map: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read + Seek,
+    F: FnMut(&Path) -> Option<PathBuf>,
+{
+    let mut budget = Budget::new(limits);
+
+    if archive.len() > limits.max_entries {
+        return Err(ArchiveError::TooManyEntries(limits.max_entries));
+    }
+
+    std::fs::create_dir_all(dest).map_err(ArchiveError::Filesystem)?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(ArchiveError::Zip)?;
+
+        let rel = file
+            .enclosed_name()
+            .and_then(|p| relative_path(&p))
+            .ok_or_else(|| ArchiveError::UnsafePath(PathBuf::from(file.name())))?;
+
+        let rel = match map(&rel).and_then(|p| relative_path(&p)) {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => continue,
+        };
+
+        budget.entry()?;
+
+        let out_path = dest.join(&rel);
+
+        if file.is_dir() {
+            std::fs::create_dir_all(&out_path).map_err(ArchiveError::Filesystem)?;
+            continue;
+        }
+
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent).map_err(ArchiveError::Filesystem)?;
+
+            // Earlier entries may have been links
+            if real_relative(dest, parent).is_none() {
+                return Err(ArchiveError::UnsafePath(rel));
+            }
+        }
+
+        if file.is_symlink() {
+            let mut target = String::new();
+            (&mut file)
+                .take(budget.remaining().min(4096))
+                .read_to_string(&mut target)
+                .map_err(ArchiveError::Filesystem)?;
+
+            let target = PathBuf::from(target);
+
+            check_link(dest, &rel, &target)?;
+
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &out_path).map_err(ArchiveError::Filesystem)?;
+            #[cfg(not(unix))]
+            warn!("Skipping link \"{}\" in archive", rel.display());
+
+            continue;
+        }
+
+        let mut out = std::fs::File::create(&out_path).map_err(ArchiveError::Filesystem)?;
+
+        // Declared sizes can't be trusted, so stop just after the limit
+        let written = io::copy(&mut (&mut file).take(budget.remaining() + 1), &mut out)
+            .map_err(ArchiveError::Filesystem)?;
+
+        budget.size(written)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&out_path, std::fs::Permissions::from_mode(mode & 0o777))
+                .map_err(ArchiveError::Filesystem)?;
+        }
+    }
+
+    debug!(
+        "Extracted {} entries ({} bytes) to \"{}\"",
+        budget.entries,
+        budget.size,
+        dest.display()
+    );
+
+    verify_links(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_with(
+        path: &str,
+        entry_type: tar::EntryType,
+        link: Option<&str>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+
+        header.set_entry_type(entry_type);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+
+        // `append_data` refuses unsafe paths, so write the name directly
+        let name = &mut header.as_old_mut().name;
+        name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+
+        builder.append(&header, data).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            relative_path(Path::new("./a/./b")),
+            Some(PathBuf::from("a/b"))
+        );
+        assert_eq!(relative_path(Path::new("a/../b")), None);
+        assert_eq!(relative_path(Path::new("/etc/passwd")), None);
+
+        assert!(link_stays_inside(Path::new("a/b"), Path::new("../c")));
+        assert!(link_stays_inside(Path::new("a"), Path::new("../c")));
+        assert!(!link_stays_inside(Path::new("a"), Path::new("../../c")));
+        assert!(!link_stays_inside(Path::new(""), Path::new("/etc")));
+    }
+
+    #[test]
+    fn test_unsafe_tar() {
+        let temp = tempfile::tempdir().unwrap();
+        let dest = temp.path();
+
+        let traversal = tar_with("../evil", tar::EntryType::Regular, None, b"x");
+        assert!(matches!(
+            unpack_tar(traversal.as_slice(), dest, Limits::default()),
+            Err(ArchiveError::UnsafePath(_))
+        ));
+
+        let link = tar_with("link", tar::EntryType::Symlink, Some("../../etc"), b"");
+        assert!(matches!(
+            unpack_tar(link.as_slice(), dest, Limits::default()),
+            Err(ArchiveError::UnsafeLink(..))
+        ));
+
+        let large = tar_with("large", tar::EntryType::Regular, None, &[0; 64]);
+        let limits = Limits {
+            max_entries: 10,
+            max_size: 32,
+        };
+        assert!(matches!(
+            unpack_tar(large.as_slice(), dest, limits),
+            Err(ArchiveError::TooLarge(32))
+        ));
+
+        let ok = tar_with("dir/file", tar::EntryType::Regular, None, b"hello");
+        unpack_tar(ok.as_slice(), dest, Limits::default()).unwrap();
+        assert_eq!(std::fs::read(dest.join("dir/file")).unwrap(), b"hello");
+
+        assert!(!dest.parent().unwrap().join("evil").exists());
+    }
+
+    fn zip_with<F: FnOnce(&mut zip::ZipWriter<io::Cursor<Vec<u8>>>)>(add: F) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        add(&mut writer);
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Overwrites the uncompressed size in the local and central headers
+    fn declare_size(zip: &mut [u8], size: u32) {
+        for (signature, offset) in [(0x04034b50u32, 22), (0x02014b50, 24)] {
+            let start = zip
+                .windows(4)
+                .position(|w| w == signature.to_le_bytes())
+                .unwrap();
+
+            zip[start + offset..start + offset + 4].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_unsafe_zip() {
+        use std::io::Write;
+        use zip::write::SimpleFileOptions;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dest = temp.path().join("dest");
+        let options = SimpleFileOptions::default();
+
+        let traversal = zip_with(|w| {
+            w.start_file("../evil", options).unwrap();
+            w.write_all(b"x").unwrap();
+        });
+        assert!(matches!(
+            unpack_zip(io::Cursor::new(traversal), &dest, Limits::default()),
+            Err(ArchiveError::UnsafePath(_))
+        ));
+
+        let link = zip_with(|w| {
+            w.add_symlink("dir/link", "../../etc", options).unwrap();
+        });
+        assert!(matches!(
+            unpack_zip(io::Cursor::new(link), &dest, Limits::default()),
+            Err(ArchiveError::UnsafeLink(..))
+        ));
+
+        // Declares 8 bytes, but decompresses to 64
+        let mut large = zip_with(|w| {
+            w.start_file(
+                "large",
+                options.compression_method(zip::CompressionMethod::Deflated),
+            )
+            .unwrap();
+            w.write_all(&[0; 64]).unwrap();
+        });
+        declare_size(&mut large, 8);
+
+        let limits = Limits {
+            max_entries: 10,
+            max_size: 32,
+        };
+        assert!(matches!(
+            unpack_zip(io::Cursor::new(large), &dest, limits),
+            Err(ArchiveError::TooLarge(32))
+        ));
+
+        let ok = zip_with(|w| {
+            w.start_file("dir/file", options).unwrap();
+            w.write_all(b"hello").unwrap();
+        });
+        unpack_zip(io::Cursor::new(ok), &dest, Limits::default()).unwrap();
+        assert_eq!(std::fs::read(dest.join("dir/file")).unwrap(), b"hello");
+
+        assert!(!temp.path().join("evil").exists());
+    }
+}
@@ -34,6 +34,9 @@ pub struct BuildInfo {
     #[serde(rename = "job-progress")]
     pub job_progress: usize,
     pub exec: Option<exec::BuildExecInfo>,
+    /// Resolved RCON port and password variables, if set
+    #[serde(default, skip_serializing_if = "template::var::VarMap::is_empty")]
+    pub variables: template::var::VarMap,
 }
 
 impl BuildInfo {
@@ -0,0 +1,356 @@
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::{fs, process::Command};
+use tracing::{debug, info};
+
+use crate::netconf;
+use crate::vkstore::VolkanicStore;
+
+use super::archive;
+
+const GIT_CACHE_SUFFIX: &str = "git/";
+/// Transports templates may use. Others, like `ext::`, can run commands
+const ALLOWED_PROTOCOLS: &[&str] = &["https", "http", "ssh", "git", "file"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum GitError {
+    #[error("Failed to run git (is it installed?): {0}")]
+    Spawn(std::io::Error),
+    #[error("git {0} failed: {1}")]
+    Command(String, String),
+    #[error("Unsupported or unsafe repository URL: {0}")]
+    InvalidUrl(String),
+    #[error("Reference not found in repository: {0}")]
+    RefNotFound(String),
+    #[error("Commit mismatch (expected: {expected}, found: {found})")]
+    CommitMismatch { expected: String, found: String },
+    #[error("Filesystem error: {0}")]
+    Filesystem(std::io::Error),
+    #[error("Archive error: {0}")]
+    Archive(archive::ArchiveError),
+    #[error("Network configuration error: {0}")]
+    NetConfig(netconf::NetConfError),
+}
+
+/// Creates a git command that never prompts for credentials
+fn git() -> Command {
+    let mut command = Command::new("git");
+
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_ALLOW_PROTOCOL", ALLOWED_PROTOCOLS.join(":"));
+
+    command
+}
+
+/// Creates a git command for accessing a remote, using the network
+/// configuration's proxy and authentication
+fn remote_git(url: &str) -> Result<Command, GitError> {
+    let mut command = git();
+
+    command.envs(
+        netconf::NetConfig::get()
+            .map_err(GitError::NetConfig)?
+            .git_env(url),
+    );
+
+    Ok(command)
+}
+
+/// Runs a git command and returns its standard output
+async fn run(command: &mut Command) -> Result<Vec<u8>, GitError> {
+    let output = command.output().await.map_err(GitError::Spawn)?;
+
+    if !output.status.success() {
+        let args = command
+            .as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        return Err(GitError::Command(
+            args,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Checks that a URL uses an allowed protocol and can't be mistaken for an
+/// option, as template URLs are untrusted
+fn check_url(url: &str) -> Result<(), GitError> {
+    let invalid = || GitError::InvalidUrl(url.to_string());
+
+    if url.starts_with('-') || url.contains("::") {
+        return Err(invalid());
+    }
+
+    match url.split_once("://") {
+        Some((scheme, _)) => {
+            if !ALLOWED_PROTOCOLS
+                .iter()
+                .any(|p| p.eq_ignore_ascii_case(scheme))
+            {
+                return Err(invalid());
+            }
+        }
+        // SCP-like syntax for SSH (e.g. "git@github.com:user/repo.git")
+        None => match url.split_once(':') {
+            Some((host, _)) if !host.is_empty() && !host.contains('/') => {}
+            _ => return Err(invalid()),
+        },
+    }
+
+    Ok(())
+}
+
+/// Path of the cached bare clone for a repository URL
+pub fn cache_path(store: &VolkanicStore, url: &str) -> PathBuf {
+    store.downloads_path.join(GIT_CACHE_SUFFIX).join(format!(
+        "{}.git",
+        hex::encode(Sha256::digest(url.as_bytes()))
+    ))
+}
+
+/// Whether the reference is a full SHA-1 or SHA-256 commit hash
+fn is_commit_hash(reference: &str) -> bool {
+    matches!(reference.len(), 40 | 64) && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Resolves a reference to a full commit hash, if present in the clone
+async fn resolve(repo: &Path, reference: &str) -> Option<String> {
+    let output = run(git()
+        .arg("--git-dir")
+        .arg(repo)
+        .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+        .arg(format!("{}^{{commit}}", reference)))
+    .await
+    .ok()?;
+
+    Some(String::from_utf8_lossy(&output).trim().to_string())
+}
+
+/// Clones or updates the bare clone of a repository in `repo` and resolves
+/// the reference (commit hash, tag or branch) to a commit hash
+///
+/// If `commit` is provided, the resolved hash must match it.
+pub async fn fetch(
+    repo: &Path,
+    url: &str,
+    reference: &str,
+    commit: Option<&str>,
+) -> Result<String, GitError> {
+    check_url(url)?;
+
+    if repo.is_dir() {
+        // Commits never change, so there's nothing to fetch if already cached
+        let cached = is_commit_hash(reference) && resolve(repo, reference).await.is_some();
+
+        if !cached {
+            info!("Fetching \"{}\"", url);
+
+            run(remote_git(url)?
+                .arg("--git-dir")
+                .arg(repo)
+                .args(["fetch", "--quiet", "--prune", "--tags", "--", url])
+                .arg("+refs/heads/*:refs/heads/*"))
+            .await?;
+        }
+    } else {
+        info!("Cloning \"{}\"", url);
+
+        if let Some(parent) = repo.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(GitError::Filesystem)?;
+        }
+
+        run(remote_git(url)?
+            .args(["clone", "--bare", "--quiet", "--", url])
+            .arg(repo))
+        .await?;
+    }
+
+    let found = resolve(repo, reference)
+        .await
+        .ok_or_else(|| GitError::RefNotFound(reference.to_string()))?;
+
+    debug!("\"{}\" resolved to commit {}", reference, found);
+
+    if let Some(expected) = commit {
+        if !found.eq_ignore_ascii_case(expected) {
+            return Err(GitError::CommitMismatch {
+                expected: expected.to_string(),
+                found,
+            });
+        }
+    }
+
+    Ok(found)
+}
+
+/// Writes the files of a commit (or a subdirectory of it) to `dest`
+///
+/// Submodules are not included.
+pub async fn export(
+    repo: &Path,
+    commit: &str,
+    subdirectory: Option<&Path>,
+    dest: &Path,
+) -> Result<(), GitError> {
+    let tree = match subdirectory {
+        Some(s) => format!("{}:{}", commit, s.to_string_lossy()),
+        None => commit.to_string(),
+    };
+
+    let archive = run(git()
+        .arg("--git-dir")
+        .arg(repo)
+        .args(["archive", "--format=tar"])
+        .arg(tree))
+    .await?;
+
+    fs::create_dir_all(dest)
+        .await
+        .map_err(GitError::Filesystem)?;
+
+    let dest = dest.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        archive::unpack_tar(archive.as_slice(), &dest, archive::Limits::default())
+    })
+    .await
+    .map_err(|e| GitError::Filesystem(e.into()))?
+    .map_err(GitError::Archive)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a repository with a tagged commit and returns its path and
+    /// commit hash
+    async fn fixture(root: &Path) -> (PathBuf, String) {
+        let work = root.join("work");
+
+        fs::create_dir_all(work.join("datapacks/example"))
+            .await
+            .unwrap();
+        fs::write(work.join("README.md"), "Example\n")
+            .await
+            .unwrap();
+        fs::write(work.join("datapacks/example/pack.mcmeta"), "{}\n")
+            .await
+            .unwrap();
+
+        let in_work = |args: &[&str]| {
+            let mut command = git();
+
+            command
+                .arg("-C")
+                .arg(&work)
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args);
+
+            command
+        };
+
+        run(&mut in_work(&["init", "--quiet"])).await.unwrap();
+        run(&mut in_work(&["add", "."])).await.unwrap();
+        run(&mut in_work(&["commit", "--quiet", "-m", "Initial commit"]))
+            .await
+            .unwrap();
+        run(&mut in_work(&["tag", "v1"])).await.unwrap();
+
+        let commit = String::from_utf8(run(&mut in_work(&["rev-parse", "HEAD"])).await.unwrap())
+            .unwrap()
+            .trim()
+            .to_string();
+
+        let bare = root.join("origin.git");
+
+        run(git()
+            .args(["clone", "--bare", "--quiet"])
+            .arg(&work)
+            .arg(&bare))
+        .await
+        .unwrap();
+
+        (bare, commit)
+    }
+
+    #[test]
+    fn test_check_url() {
+        for url in [
+            "https://github.com/user/repo.git",
+            "ssh://git@example.com/repo.git",
+            "git@github.com:user/repo.git",
+            "file:///srv/repo.git",
+        ] {
+            assert!(check_url(url).is_ok(), "{}", url);
+        }
+
+        for url in [
+            "--upload-pack=sh -c 'touch /tmp/pwned'",
+            "-c",
+            "ext::sh -c touch% /tmp/pwned",
+            "fd::17",
+            "/srv/repo.git",
+            "./repo",
+            "gopher://example.com/repo",
+        ] {
+            assert!(
+                matches!(check_url(url), Err(GitError::InvalidUrl(_))),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_and_export() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let (origin, commit) = fixture(root).await;
+        let url = format!("file://{}", origin.to_string_lossy());
+        let cache = root.join("cache.git");
+
+        let found = fetch(&cache, &url, "v1", Some(&commit)).await.unwrap();
+        assert_eq!(found, commit);
+
+        // Second fetch updates the existing clone
+        assert_eq!(fetch(&cache, &url, "v1", None).await.unwrap(), commit);
+
+        assert!(matches!(
+            fetch(
+                &cache,
+                &url,
+                "v1",
+                Some("0000000000000000000000000000000000000000")
+            )
+            .await,
+            Err(GitError::CommitMismatch { .. })
+        ));
+        assert!(matches!(
+            fetch(&cache, &url, "missing", None).await,
+            Err(GitError::RefNotFound(_))
+        ));
+
+        let dest = root.join("out");
+        export(&cache, &commit, Some(Path::new("datapacks")), &dest)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("example/pack.mcmeta"))
+                .await
+                .unwrap(),
+            "{}\n"
+        );
+        assert!(!dest.join("README.md").exists());
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::Path;
+use tracing::info;
+
+use crate::fsobj;
+use crate::vkstore::VolkanicStore;
+
+use super::{git, Error};
+
+pub async fn clone_git<P: AsRef<Path>>(
+    store: &VolkanicStore,
+    url: &str,
+    reference: &str,
+    commit: Option<&str>,
+    subdirectory: Option<&Path>,
+    template_path: P,
+) -> Result<(), Error> {
+    let repo = git::cache_path(store, url);
+
+    let resolved = git::fetch(&repo, url, reference, commit)
+        .await
+        .map_err(Error::Git)?;
+
+    info!("Using commit {} of \"{}\"", resolved, url);
+
+    git::export(
+        &repo,
+        &resolved,
+        subdirectory,
+        &fsobj::safe_join(&store.build_path, template_path).map_err(Error::UnsafePath)?,
+    )
+    .await
+    .map_err(Error::Git)?;
+
+    Ok(())
+}
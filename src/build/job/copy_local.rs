@@ -0,0 +1,156 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+use tokio::fs;
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+use crate::fsobj;
+use crate::vkstore::VolkanicStore;
+
+use super::Error;
+
+/// Compiles glob patterns matched against paths relative to a copied
+/// directory
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    for p in patterns {
+        builder.add(
+            GlobBuilder::new(p)
+                .literal_separator(true)
+                .build()
+                .map_err(Error::InvalidGlob)?,
+        );
+    }
+
+    builder.build().map_err(Error::InvalidGlob)
+}
+
+/// Copies a local file or directory into the build
+///
+/// The include and exclude patterns only apply to directories. If no include
+/// patterns are given, every file is copied.
+pub async fn copy_local<S: AsRef<Path>, P: AsRef<Path>>(
+    store: &VolkanicStore,
+    source: S,
+    include: &[String],
+    exclude: &[String],
+    template_path: P,
+) -> Result<(), Error> {
+    let source = source.as_ref();
+    let abs_path = fsobj::safe_join(&store.build_path, template_path).map_err(Error::UnsafePath)?;
+
+    match fsobj::fs_obj(source).await {
+        fsobj::FsObjectType::File => {
+            fsobj::create_ancestors(&abs_path)
+                .await
+                .map_err(Error::CreateFilesystemAncestors)?;
+
+            fs::copy(source, &abs_path)
+                .await
+                .map_err(Error::Filesystem)?;
+        }
+        fsobj::FsObjectType::Directory => {
+            let include_set = glob_set(include)?;
+            let exclude_set = glob_set(exclude)?;
+
+            fs::create_dir_all(&abs_path)
+                .await
+                .map_err(Error::Filesystem)?;
+
+            for entry in WalkDir::new(source).min_depth(1) {
+                let entry = entry.map_err(|e| Error::Filesystem(e.into()))?;
+
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+
+                // Links could point anywhere on the host
+                if entry.path_is_symlink() {
+                    warn!("Skipping link \"{}\"", entry.path().to_string_lossy());
+                    continue;
+                }
+
+                let rel_path = match entry.path().strip_prefix(source) {
+                    Ok(p) => p,
+                    Err(_) => continue,
+                };
+
+                if (!include.is_empty() && !include_set.is_match(rel_path))
+                    || exclude_set.is_match(rel_path)
+                {
+                    debug!("Skipping \"{}\"", rel_path.to_string_lossy());
+                    continue;
+                }
+
+                let dest = fsobj::safe_join(&abs_path, rel_path).map_err(Error::UnsafePath)?;
+
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .map_err(Error::Filesystem)?;
+                }
+
+                fs::copy(entry.path(), &dest)
+                    .await
+                    .map_err(Error::Filesystem)?;
+            }
+        }
+        fsobj::FsObjectType::None => {
+            return Err(Error::LocalNotFound(source.to_path_buf()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_copy_local_links() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let store = VolkanicStore::new_custom_root(temp.path().join("store")).await;
+
+        fs::create_dir_all(source.join("config")).await.unwrap();
+        fs::create_dir_all(&store.build_path).await.unwrap();
+        fs::write(source.join("config/a.toml"), "a").await.unwrap();
+        fs::write(temp.path().join("secret"), "secret")
+            .await
+            .unwrap();
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(temp.path().join("secret"), source.join("secret")).unwrap();
+
+        copy_local(&store, &source, &[], &[], "copied")
+            .await
+            .unwrap();
+
+        let dest = store.build_path.join("copied");
+
+        assert_eq!(
+            fs::read_to_string(dest.join("config/a.toml"))
+                .await
+                .unwrap(),
+            "a"
+        );
+        assert!(!dest.join("secret").exists());
+    }
+
+    #[test]
+    fn test_glob_set() {
+        let set = glob_set(&["*.yml".to_string(), "config/**/*.toml".to_string()]).unwrap();
+
+        assert!(set.is_match("bukkit.yml"));
+        assert!(!set.is_match("plugins/bukkit.yml"));
+        assert!(set.is_match("config/mod.toml"));
+        assert!(set.is_match("config/a/b/mod.toml"));
+
+        assert!(matches!(
+            glob_set(&["[".to_string()]),
+            Err(Error::InvalidGlob(_))
+        ));
+    }
+}
@@ -1,15 +1,19 @@
-use tracing::error;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
 
-use crate::resources::JdkLookup;
+use crate::fsobj;
+use crate::resources::{self, JdkLookup, RemoteFile};
 use crate::template::{self, vkinclude};
 
-use super::{Job, JobAction, Error};
+use super::{copy_local::glob_set, misc, Job, JobAction, Error};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create_jobs(
     template: &crate::template::Template,
     overlays: &Vec<template::overlay::Overlay>,
     jdk_lookup: JdkLookup,
     var_map: &template::var::VarMap,
+    include_dir: Option<&Path>,
     no_verify: bool,
     force_jdk_distribution: Option<String>,
     preferred_distributions: Option<Vec<String>>,
@@ -74,33 +78,62 @@ pub async fn create_jobs(
         }
     }
 
-    let mut resources = template.resources.clone();
+    // Each resource is paired with the directory of the file it came from
+    let mut resources: Vec<_> = template
+        .resources
+        .iter()
+        .map(|r| (r.clone(), &template.source_dir))
+        .collect();
 
     for o in overlays {
-        resources.extend(o.resources.clone());
+        resources.extend(o.resources.iter().map(|r| (r.clone(), &o.source_dir)));
     }
 
     // Setup additional resources
-    for resource in resources {
+    for (resource, source_dir) in resources {
+        if let Some(condition) = resource.condition() {
+            if !condition.evaluate(var_map).map_err(Error::Condition)? {
+                info!(
+                    "Skipping resource \"{}\" as its condition is not met",
+                    resource.template_path().to_string_lossy()
+                );
+                continue;
+            }
+        }
+
         match resource {
             template::resource::GenericResource::Remote {
                 url,
+                mirrors,
                 user_agent,
                 override_name,
                 sha512,
+                sha256,
+                sha1,
                 use_variables,
                 archive,
                 template_path: path,
+                ..
             } => {
+                check_remote_file(
+                    override_name.as_ref(),
+                    sha512.as_ref(),
+                    sha256.as_ref(),
+                    sha1.as_ref(),
+                )?;
+
                 jobs.push(Job {
                     title: "Download additional resource".into(),
                     action: JobAction::WriteFileRemote {
                         path: path.clone(),
                         url: url.clone(),
+                        mirrors: mirrors.clone(),
                         user_agent: user_agent.clone(),
                         override_name: override_name.clone(),
                         archive: archive.clone(),
                         sha512: sha512.clone(),
+                        sha256: sha256.clone(),
+                        sha1: sha1.clone(),
                     },
                 });
 
@@ -130,6 +163,7 @@ pub async fn create_jobs(
                 base64: base,
                 use_variables,
                 template_path,
+                ..
             } => {
                 jobs.push(Job {
                     title: "Write file from Base64".into(),
@@ -154,21 +188,31 @@ pub async fn create_jobs(
                 include_id,
                 use_variables,
                 template_path,
+                ..
             } => {
                 // Pre-checks
-                let include = vkinclude::VolkanicInclude::new().await;
-                if include.get(&include_id).await.is_none() {
-                    error!("Did not find \"{}\" in include directory.", include_id);
-                    return Err(Error::NotAvailableInIncludeFolder(
-                        include_id.to_string(),
-                    ));
+                let mut include = vkinclude::VolkanicInclude::new(source_dir).await;
+                if let Some(include_dir) = include_dir {
+                    include = include.override_path(include_dir).await;
                 }
 
+                let source = match include.get(&include_id).await {
+                    Some(p) => std::path::absolute(p).map_err(Error::Filesystem)?,
+                    None => {
+                        error!("Did not find \"{}\" in include directory.", include_id);
+                        return Err(Error::NotAvailableInIncludeFolder(
+                            include_id.to_string(),
+                        ));
+                    }
+                };
+
                 // Push job
                 jobs.push(Job {
                     title: "Copy additional resource".into(),
-                    action: JobAction::CopyFromInclude {
-                        id: include_id.to_string(),
+                    action: JobAction::CopyLocal {
+                        source,
+                        include: vec![],
+                        exclude: vec![],
                         template_path: template_path.clone(),
                     },
                 });
@@ -184,8 +228,275 @@ pub async fn create_jobs(
                     })
                 }
             }
+            template::resource::GenericResource::Local {
+                path,
+                include,
+                exclude,
+                use_variables,
+                template_path,
+                ..
+            } => {
+                // Local resources can't read host files outside the template
+                let source = fsobj::safe_join(source_dir, &path).map_err(Error::UnsafePath)?;
+
+                let source = match fsobj::fs_obj(&source).await {
+                    fsobj::FsObjectType::None => {
+                        error!(
+                            "Did not find local resource \"{}\"",
+                            source.to_string_lossy()
+                        );
+                        return Err(Error::LocalNotFound(source));
+                    }
+                    fsobj::FsObjectType::Directory if use_variables.is_some() => {
+                        error!("Variable substitution is not supported for directories");
+                        return Err(Error::DirectoriesCannotHaveVariables(template_path));
+                    }
+                    _ => std::path::absolute(&source).map_err(Error::Filesystem)?,
+                };
+
+                // Catch invalid patterns before any jobs run
+                glob_set(&include)?;
+                glob_set(&exclude)?;
+
+                jobs.push(Job {
+                    title: "Copy local resource".into(),
+                    action: JobAction::CopyLocal {
+                        source,
+                        include,
+                        exclude,
+                        template_path: template_path.clone(),
+                    },
+                });
+
+                if let Some(use_variables) = use_variables {
+                    jobs.push(Job {
+                        title: "Perform variable substitution".into(),
+                        action: JobAction::ProcessVariables {
+                            path: template_path,
+                            format: use_variables,
+                            variables: var_map.clone(),
+                        },
+                    })
+                }
+            }
+            template::resource::GenericResource::Maven {
+                repository,
+                group,
+                artifact,
+                version,
+                classifier,
+                extension,
+                template_path,
+                ..
+            } => {
+                jobs.push(Job {
+                    title: "Download Maven artifact".into(),
+                    action: JobAction::DownloadMaven {
+                        path: template_path,
+                        repository,
+                        group,
+                        artifact,
+                        version,
+                        classifier,
+                        extension: extension.unwrap_or_else(|| "jar".into()),
+                        no_verify,
+                    },
+                });
+            }
+            template::resource::GenericResource::Hangar {
+                project,
+                version,
+                platform,
+                platform_version,
+                channel,
+                template_path,
+                ..
+            } => {
+                let file = resources::hangar::resolve(
+                    &project,
+                    version.as_deref(),
+                    &platform,
+                    platform_version.as_deref(),
+                    channel.as_deref(),
+                )
+                .await
+                .map_err(Error::PluginLookup)?;
+
+                push_remote_file_job(&mut jobs, template_path, file)?;
+            }
+            template::resource::GenericResource::Spiget {
+                resource: id,
+                version,
+                template_path,
+                ..
+            } => {
+                let file = resources::spiget::resolve(id, version)
+                    .await
+                    .map_err(Error::PluginLookup)?;
+
+                push_remote_file_job(&mut jobs, template_path, file)?;
+            }
+            template::resource::GenericResource::Git {
+                url,
+                reference,
+                commit,
+                subdirectory,
+                template_path,
+                ..
+            } => {
+                jobs.push(Job {
+                    title: "Copy files from git repository".into(),
+                    action: JobAction::CloneGit {
+                        url,
+                        reference,
+                        commit,
+                        subdirectory,
+                        template_path,
+                    },
+                });
+            }
+            template::resource::GenericResource::Text {
+                content,
+                use_variables,
+                template_path,
+                ..
+            } => {
+                push_text_jobs(
+                    &mut jobs,
+                    template_path,
+                    content.text(),
+                    use_variables,
+                    var_map,
+                );
+            }
+            template::resource::GenericResource::Json {
+                value,
+                use_variables,
+                template_path,
+                ..
+            } => {
+                let mut contents =
+                    serde_jsonc::to_string_pretty(&value).map_err(Error::JsonSerialize)?;
+                contents.push('\n');
+
+                push_text_jobs(&mut jobs, template_path, contents, use_variables, var_map);
+            }
+            template::resource::GenericResource::Yaml {
+                value,
+                use_variables,
+                template_path,
+                ..
+            } => {
+                let contents = serde_yaml::to_string(&value).map_err(Error::YamlSerialize)?;
+
+                push_text_jobs(&mut jobs, template_path, contents, use_variables, var_map);
+            }
+            template::resource::GenericResource::Patch {
+                format,
+                set,
+                remove,
+                template_path,
+                ..
+            } => {
+                let format = match format {
+                    Some(format) => format,
+                    None => match template::resource::PatchFormat::from_path(&template_path) {
+                        Some(format) => format,
+                        None => {
+                            error!(
+                                "Unable to detect format of \"{}\", please specify one",
+                                template_path.to_string_lossy()
+                            );
+                            return Err(Error::UnknownPatchFormat(template_path));
+                        }
+                    },
+                };
+
+                jobs.push(Job {
+                    title: "Patch file".into(),
+                    action: JobAction::PatchFile {
+                        path: template_path,
+                        format,
+                        set,
+                        remove,
+                    },
+                });
+            }
         }
     }
 
     Ok(jobs)
 }
+
+/// Checks the name and hashes of a remote file, as they name its download
+/// in the store
+fn check_remote_file(
+    override_name: Option<&String>,
+    sha512: Option<&String>,
+    sha256: Option<&String>,
+    sha1: Option<&String>,
+) -> Result<(), Error> {
+    if let Some(name) = override_name {
+        misc::check_file_name(name).map_err(Error::Download)?;
+    }
+
+    for v in misc::Verification::from_hashes(sha512, sha256, sha1) {
+        v.check().map_err(Error::Download)?;
+    }
+
+    Ok(())
+}
+
+/// Pushes a job to download a file resolved from a plugin repository
+fn push_remote_file_job(
+    jobs: &mut Vec<Job>,
+    template_path: PathBuf,
+    file: RemoteFile,
+) -> Result<(), Error> {
+    check_remote_file(Some(&file.file_name), None, file.sha256.as_ref(), None)?;
+
+    jobs.push(Job {
+        title: "Download plugin".into(),
+        action: JobAction::WriteFileRemote {
+            path: template_path,
+            archive: None,
+            url: file.url,
+            mirrors: vec![],
+            sha512: None,
+            sha256: file.sha256,
+            sha1: None,
+            user_agent: None,
+            override_name: Some(file.file_name),
+        },
+    });
+
+    Ok(())
+}
+
+/// Pushes jobs to write a text file and optionally substitute its variables
+fn push_text_jobs(
+    jobs: &mut Vec<Job>,
+    template_path: PathBuf,
+    contents: String,
+    use_variables: Option<template::var::VarFormat>,
+    var_map: &template::var::VarMap,
+) {
+    jobs.push(Job {
+        title: "Write text file".into(),
+        action: JobAction::WriteFileText {
+            path: template_path.clone(),
+            contents,
+        },
+    });
+
+    if let Some(use_variables) = use_variables {
+        jobs.push(Job {
+            title: "Perform variable substitution".into(),
+            action: JobAction::ProcessVariables {
+                path: template_path,
+                format: use_variables,
+                variables: var_map.clone(),
+            },
+        })
+    }
+}
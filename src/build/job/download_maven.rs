@@ -0,0 +1,49 @@
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::build::{maven, misc};
+use crate::fsobj;
+use crate::netconf;
+use crate::vkstore::VolkanicStore;
+
+use super::Error;
+
+pub async fn download_maven<P: AsRef<Path>>(
+    store: &VolkanicStore,
+    template_path: P,
+    artifact: maven::Artifact<'_>,
+    no_verify: bool,
+) -> Result<(), Error> {
+    let abs_path = fsobj::safe_join(&store.build_path, template_path).map_err(Error::UnsafePath)?;
+
+    fsobj::create_ancestors(&abs_path)
+        .await
+        .map_err(Error::CreateFilesystemAncestors)?;
+
+    let client = netconf::client().map_err(|e| Error::Maven(maven::MavenError::NetConfig(e)))?;
+
+    let url = artifact.resolve_url(&client).await.map_err(Error::Maven)?;
+
+    info!("Resolved Maven artifact \"{}\"", url);
+
+    let verification = if no_verify {
+        warn!("Skipping checksum lookup for \"{}\"", url);
+        misc::Verification::None
+    } else {
+        maven::checksum(&client, &url).await.map_err(Error::Maven)?
+    };
+
+    let name = match misc::get_remote_filename(&url).await {
+        Some(name) => name,
+        None => return Err(Error::NoFileNameInPath(abs_path)),
+    };
+
+    let p = misc::download_progress(store.clone(), &url, verification, name, None::<String>)
+        .await
+        .map_err(Error::Download)?;
+
+    fs::copy(p, abs_path).await.map_err(Error::Filesystem)?;
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::path;
+use std::{collections::BTreeMap, path};
 use tokio::fs;
 use tracing::error;
 
@@ -10,21 +10,32 @@ use crate::template;
 use crate::vkstore;
 
 use super::buildinfo;
+use super::git;
+use super::maven;
 use super::misc;
+use super::patch;
 use super::prepare_jdk;
 
-mod copy_include;
+mod clone_git;
+mod copy_local;
 mod create_jobs;
+mod download_maven;
+mod patch_file;
 mod process_vars;
 mod write_base;
 mod write_remote;
+mod write_text;
 
 pub use create_jobs::create_jobs;
 
-use copy_include::copy_include;
+use clone_git::clone_git;
+use copy_local::copy_local;
+use download_maven::download_maven;
+use patch_file::patch_file;
 use process_vars::process_vars;
 use write_base::write_base64;
 use write_remote::write_remote;
+use write_text::write_text;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -32,12 +43,18 @@ pub enum Error {
     JdkNotFound(String),
     #[error("Foojay Disco lookup error: {0}")]
     DiscoLookup(resources::Error),
+    #[error("Plugin lookup error: {0}")]
+    PluginLookup(resources::Error),
     #[error("Filesystem error: {0}")]
     Filesystem(tokio::io::Error),
     #[error("Inner archive path doesn't exist: {0}")]
     InnerArchivePathNotFound(path::PathBuf),
+    #[error("Inner archive path pattern \"{0}\" matches more than one entry: {1:?}")]
+    AmbiguousInnerArchivePath(path::PathBuf, Vec<path::PathBuf>),
     #[error("Extraction error: {0}")]
     Extraction(misc::ExtractionError),
+    #[error("Unsafe path: {0}")]
+    UnsafePath(fsobj::UnsafePathError),
     #[error("No file name found in path: {0}")]
     NoFileNameInPath(path::PathBuf),
     #[error("Creating path ancestor directories failed: {0}")]
@@ -54,10 +71,36 @@ pub enum Error {
     BuildInfo(buildinfo::BuildInfoError),
     #[error("Not available in Volkanic include folder: {0}")]
     NotAvailableInIncludeFolder(String),
+    #[error("Local resource not found: {0}")]
+    LocalNotFound(path::PathBuf),
+    #[error("Invalid glob pattern: {0}")]
+    InvalidGlob(globset::Error),
+    #[error("Directories cannot have variables (resource path: {0})")]
+    DirectoriesCannotHaveVariables(path::PathBuf),
+    #[error("Git error: {0}")]
+    Git(git::GitError),
+    #[error("Maven error: {0}")]
+    Maven(maven::MavenError),
     #[error("Archives cannot have variables (resource path: {0})")]
     ArchivesCannotHaveVariables(path::PathBuf),
+    #[error("Include and exclude patterns cannot apply to files (inner archive path: {0})")]
+    FilesCannotHaveSelection(path::PathBuf),
     #[error("Conflicting overlay runtimes")]
     ConflictingRuntimes,
+    #[error("Variable processing error: {0}")]
+    VarProcess(template::var::VarProcessError),
+    #[error("Resource condition error: {0}")]
+    Condition(template::condition::ConditionError),
+    #[error("Patch error: {0}")]
+    Patch(patch::PatchError),
+    #[error("Unable to detect patch format for: {0}")]
+    UnknownPatchFormat(path::PathBuf),
+    #[error("Cannot patch a directory: {0}")]
+    CannotPatchDirectory(path::PathBuf),
+    #[error("Failed to serialize JSON: {0}")]
+    JsonSerialize(serde_jsonc::Error),
+    #[error("Failed to serialize YAML: {0}")]
+    YamlSerialize(serde_yaml::Error),
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -71,6 +114,12 @@ pub enum JobAction {
         path: path::PathBuf,
         contents: String,
     },
+    /// Create a file with text contents
+    #[serde(rename = "create-file-text")]
+    WriteFileText {
+        path: path::PathBuf,
+        contents: String,
+    },
     /// Download a file
     #[serde(rename = "download-file")]
     WriteFileRemote {
@@ -78,24 +127,67 @@ pub enum JobAction {
         /// If format is not defined, the file is only copied and not decompressed.
         archive: Option<template::resource::ArchiveInfo>,
         url: String,
+        /// URLs tried in order if downloading from `url` fails
+        #[serde(default)]
+        mirrors: Vec<String>,
         sha512: Option<String>,
+        #[serde(default)]
+        sha256: Option<String>,
+        #[serde(default)]
+        sha1: Option<String>,
         #[serde(rename = "user-agent")]
         user_agent: Option<String>,
         #[serde(rename = "override-name")]
         override_name: Option<String>,
     },
-    /// Copy a file
-    #[serde(rename = "from-include")]
-    CopyFromInclude {
-        id: String,
+    /// Copy a local file or directory
+    #[serde(rename = "copy-local")]
+    CopyLocal {
+        source: path::PathBuf,
+        include: Vec<String>,
+        exclude: Vec<String>,
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+    },
+    /// Download an artifact from a Maven repository
+    #[serde(rename = "download-maven")]
+    DownloadMaven {
+        path: path::PathBuf,
+        repository: String,
+        group: String,
+        artifact: String,
+        version: String,
+        classifier: Option<String>,
+        extension: String,
+        no_verify: bool,
+    },
+    /// Copy files from a git repository
+    #[serde(rename = "clone-git")]
+    CloneGit {
+        url: String,
+        #[serde(rename = "ref")]
+        reference: String,
+        commit: Option<String>,
+        subdirectory: Option<path::PathBuf>,
         #[serde(rename = "template-path")]
         template_path: path::PathBuf,
     },
     ProcessVariables {
         path: path::PathBuf,
         format: template::var::VarFormat,
+        /// Never written to the build info, as values may be secret. Jobs
+        /// only run right after they're created.
+        #[serde(skip)]
         variables: template::var::VarMap,
     },
+    /// Set or remove keys in a structured file
+    #[serde(rename = "patch-file")]
+    PatchFile {
+        path: path::PathBuf,
+        format: template::resource::PatchFormat,
+        set: BTreeMap<String, serde_jsonc::Value>,
+        remove: Vec<String>,
+    },
     /// Setup JDK
     #[serde(rename = "prepare-jdk")]
     PrepareJdk { jdk: Jdk, no_verify: bool },
@@ -107,9 +199,12 @@ impl JobAction {
             JobAction::CreateDir {
                 path: template_path,
             } => {
-                fs::create_dir_all(store.build_path.join(template_path))
-                    .await
-                    .map_err(Error::Filesystem)?;
+                fs::create_dir_all(
+                    fsobj::safe_join(&store.build_path, template_path)
+                        .map_err(Error::UnsafePath)?,
+                )
+                .await
+                .map_err(Error::Filesystem)?;
             }
             JobAction::WriteFileBase64 {
                 path: template_path,
@@ -117,27 +212,95 @@ impl JobAction {
             } => {
                 write_base64(store, template_path, contents).await?;
             }
+            JobAction::WriteFileText {
+                path: template_path,
+                contents,
+            } => {
+                write_text(store, template_path, contents).await?;
+            }
             JobAction::WriteFileRemote {
                 path: template_path,
                 archive,
                 url,
+                mirrors,
                 sha512,
+                sha256,
+                sha1,
                 user_agent,
                 override_name,
             } => {
+                let urls: Vec<&str> = std::iter::once(url)
+                    .chain(mirrors)
+                    .map(String::as_str)
+                    .collect();
+
+                // Strongest hash first, as it identifies the download
+                let verifications = misc::Verification::from_hashes(
+                    sha512.as_ref(),
+                    sha256.as_ref(),
+                    sha1.as_ref(),
+                );
+
                 write_remote(
                     store,
                     template_path,
                     archive.as_ref(),
-                    url,
-                    sha512.as_ref(),
+                    &urls,
+                    &verifications,
                     user_agent.as_ref(),
                     override_name.as_ref(),
                 )
                 .await?;
             }
-            JobAction::CopyFromInclude { id, template_path } => {
-                copy_include(store, id, template_path).await?;
+            JobAction::CopyLocal {
+                source,
+                include,
+                exclude,
+                template_path,
+            } => {
+                copy_local(store, source, include, exclude, template_path).await?;
+            }
+            JobAction::DownloadMaven {
+                path,
+                repository,
+                group,
+                artifact,
+                version,
+                classifier,
+                extension,
+                no_verify,
+            } => {
+                download_maven(
+                    store,
+                    path,
+                    maven::Artifact {
+                        repository,
+                        group,
+                        artifact,
+                        version,
+                        classifier: classifier.as_deref(),
+                        extension,
+                    },
+                    *no_verify,
+                )
+                .await?;
+            }
+            JobAction::CloneGit {
+                url,
+                reference,
+                commit,
+                subdirectory,
+                template_path,
+            } => {
+                clone_git(
+                    store,
+                    url,
+                    reference,
+                    commit.as_deref(),
+                    subdirectory.as_deref(),
+                    template_path,
+                )
+                .await?;
             }
             JobAction::ProcessVariables {
                 path,
@@ -146,6 +309,14 @@ impl JobAction {
             } => {
                 process_vars(store, format.clone(), path, variables).await?;
             }
+            JobAction::PatchFile {
+                path,
+                format,
+                set,
+                remove,
+            } => {
+                patch_file(store, path, format, set, remove).await?;
+            }
             JobAction::PrepareJdk { jdk, no_verify } => {
                 prepare_jdk::prepare_jdk(store.clone(), jdk.clone(), *no_verify)
                     .await
@@ -179,3 +350,25 @@ pub async fn execute_jobs(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables_not_serialized() {
+        let job = Job {
+            title: "Perform variable substitution".into(),
+            action: JobAction::ProcessVariables {
+                path: "server.properties".into(),
+                format: template::var::VarFormat::DollarCurly,
+                variables: [("RCON_PASSWORD".to_string(), "hunter2".to_string())].into(),
+            },
+        };
+
+        let json = serde_jsonc::to_string(&job).unwrap();
+
+        assert!(!json.contains("hunter2"));
+        assert!(serde_jsonc::from_str::<Job>(&json).is_ok());
+    }
+}
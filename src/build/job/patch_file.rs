@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs;
+use tracing::{debug, info};
+
+use crate::fsobj;
+use crate::template::resource::PatchFormat;
+use crate::vkstore::VolkanicStore;
+
+use super::{patch, Error};
+
+pub async fn patch_file<P: AsRef<Path>>(
+    store: &VolkanicStore,
+    template_path: P,
+    format: &PatchFormat,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> Result<(), Error> {
+    let abs_path =
+        fsobj::safe_join(&store.build_path, template_path.as_ref()).map_err(Error::UnsafePath)?;
+
+    let contents = match fsobj::fs_obj(&abs_path).await {
+        fsobj::FsObjectType::File => fs::read_to_string(&abs_path)
+            .await
+            .map_err(Error::Filesystem)?,
+        fsobj::FsObjectType::None => {
+            info!(
+                "\"{}\" doesn't exist yet, creating it",
+                template_path.as_ref().to_string_lossy()
+            );
+
+            fsobj::create_ancestors(&abs_path)
+                .await
+                .map_err(Error::CreateFilesystemAncestors)?;
+
+            String::new()
+        }
+        fsobj::FsObjectType::Directory => {
+            return Err(Error::CannotPatchDirectory(abs_path));
+        }
+    };
+
+    debug!(
+        "Patching \"{}\" ({} set, {} removed)",
+        abs_path.to_string_lossy(),
+        set.len(),
+        remove.len()
+    );
+
+    let contents = patch::patch(&contents, format, set, remove).map_err(Error::Patch)?;
+
+    fs::write(&abs_path, contents)
+        .await
+        .map_err(Error::Filesystem)?;
+
+    Ok(())
+}
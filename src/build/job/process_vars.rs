@@ -3,6 +3,7 @@ use std::path::Path;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 
+use crate::fsobj;
 use crate::template::var::{string_replace, VarFormat};
 use crate::vkstore::VolkanicStore;
 
@@ -14,13 +15,16 @@ pub async fn process_vars<P: AsRef<Path>>(
     template_path: P,
     variables: &HashMap<String, String>,
 ) -> Result<(), Error> {
-    let abs_path = store.build_path.join(template_path.as_ref());
+    let abs_path =
+        fsobj::safe_join(&store.build_path, template_path.as_ref()).map_err(Error::UnsafePath)?;
 
     let mut contents = fs::read_to_string(&abs_path)
         .await
         .map_err(Error::Filesystem)?;
 
-    contents = string_replace(contents, variables, format.clone()).await;
+    contents = string_replace(contents, variables, format.clone())
+        .await
+        .map_err(Error::VarProcess)?;
 
     let mut f = fs::File::create(&abs_path)
         .await
@@ -12,7 +12,8 @@ pub async fn write_base64<P: AsRef<Path>, T: std::fmt::Display>(
     template_path: P,
     contents: T,
 ) -> Result<(), Error> {
-    let abs_path = store.build_path.join(template_path.as_ref());
+    let abs_path =
+        fsobj::safe_join(&store.build_path, template_path.as_ref()).map_err(Error::UnsafePath)?;
 
     fsobj::create_ancestors(&abs_path)
         .await
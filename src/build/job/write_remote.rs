@@ -1,25 +1,137 @@
 use futures_util::TryFutureExt;
-use std::path::Path;
+use globset::GlobBuilder;
+use std::path::{Component, Path, PathBuf};
 use tokio::fs;
 use tracing::{debug, error, info};
+use walkdir::WalkDir;
 
 use crate::build::misc;
 use crate::fsobj;
 use crate::template::resource::ArchiveInfo;
 use crate::vkstore::VolkanicStore;
 
+use super::copy_local;
 use super::Error;
 
+/// Characters that make a path component a wildcard pattern
+const GLOB_CHARS: &[char] = &['*', '?', '[', '{'];
+
+/// Moves every entry in the directory to a new root inside `temp` with
+/// `count` leading components removed, returning the new root
+///
+/// Like tar, files with `count` or fewer components are skipped.
+fn strip_components(dir: &Path, count: usize, temp: &Path) -> Result<PathBuf, Error> {
+    if count == 0 {
+        return Ok(dir.to_path_buf());
+    }
+
+    let stripped = temp.join(format!("stripped-{}", uuid::Uuid::new_v4()));
+
+    std::fs::create_dir_all(&stripped).map_err(Error::Filesystem)?;
+
+    let entries = WalkDir::new(dir)
+        .min_depth(count)
+        .sort_by_file_name()
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::Filesystem(e.into()))?;
+
+    for entry in entries {
+        let rel_path = match entry.path().strip_prefix(dir) {
+            Ok(p) => p.components().skip(count).collect::<PathBuf>(),
+            Err(_) => continue,
+        };
+
+        if rel_path.as_os_str().is_empty() && !entry.file_type().is_dir() {
+            debug!(
+                "Skipping \"{}\" as it has too few components to strip",
+                entry.path().to_string_lossy()
+            );
+            continue;
+        }
+
+        let dest = stripped.join(rel_path);
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest).map_err(Error::Filesystem)?;
+        } else {
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent).map_err(Error::Filesystem)?;
+            }
+
+            std::fs::rename(entry.path(), &dest).map_err(Error::Filesystem)?;
+        }
+    }
+
+    Ok(stripped)
+}
+
+/// Resolves the inner path of an extracted archive, expanding wildcard
+/// components
+fn resolve_inner_path(base: &Path, inner_path: &Path) -> Result<PathBuf, Error> {
+    let mut resolved = PathBuf::new();
+
+    for component in inner_path.components() {
+        let pattern = match component {
+            Component::Normal(c) => c.to_string_lossy(),
+            _ => {
+                resolved.push(component);
+                continue;
+            }
+        };
+
+        if !pattern.contains(GLOB_CHARS) {
+            resolved.push(component);
+            continue;
+        }
+
+        let matcher = GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(Error::InvalidGlob)?
+            .compile_matcher();
+        let dir = fsobj::safe_join(base, &resolved).map_err(Error::UnsafePath)?;
+
+        let mut matches = Vec::new();
+
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(&dir).map_err(Error::Filesystem)? {
+                let name = entry.map_err(Error::Filesystem)?.file_name();
+
+                if matcher.is_match(&name) {
+                    matches.push(resolved.join(name));
+                }
+            }
+        }
+
+        matches.sort();
+
+        match matches.len() {
+            0 => return Err(Error::InnerArchivePathNotFound(dir.join(&*pattern))),
+            1 => resolved = matches.remove(0),
+            _ => {
+                return Err(Error::AmbiguousInnerArchivePath(
+                    inner_path.to_path_buf(),
+                    matches,
+                ))
+            }
+        }
+    }
+
+    fsobj::safe_join(base, resolved).map_err(Error::UnsafePath)
+}
+
 pub async fn write_remote<P: AsRef<Path>, T: std::fmt::Display>(
     store: &VolkanicStore,
     template_path: P,
     archive: Option<&ArchiveInfo>,
-    url: T,
-    sha512: Option<T>,
+    urls: &[&str],
+    verifications: &[misc::Verification],
     user_agent: Option<T>,
     override_name: Option<T>,
 ) -> Result<(), Error> {
-    let abs_path = store.build_path.join(template_path);
+    let template_path = template_path.as_ref();
+    let abs_path = fsobj::safe_join(&store.build_path, template_path).map_err(Error::UnsafePath)?;
 
     fsobj::create_ancestors(&abs_path)
         .await
@@ -29,7 +141,7 @@ pub async fn write_remote<P: AsRef<Path>, T: std::fmt::Display>(
         match override_name {
             Some(name) => name.to_string(),
             None => {
-                if let Some(name) = misc::get_remote_filename(url.to_string()).await {
+                if let Some(name) = misc::get_remote_filename(urls[0]).await {
                     name
                 } else {
                     return Err(Error::NoFileNameInPath(abs_path));
@@ -38,44 +150,53 @@ pub async fn write_remote<P: AsRef<Path>, T: std::fmt::Display>(
         }
     };
 
-    let p = misc::download_progress(
-        store.clone(),
-        url,
-        match sha512 {
-            Some(sha512) => misc::Verification::Sha512(sha512.to_string()),
-            None => misc::Verification::None,
-        },
-        name,
-        user_agent,
-    )
-    .map_err(Error::Download)
-    .await?;
+    let p = misc::download_verified(store.clone(), urls, verifications, name, user_agent)
+        .map_err(Error::Download)
+        .await?;
 
     match archive {
         Some(t) => {
             let archive_path = misc::extract(store.clone(), p, t.archive_format.clone())
                 .await
                 .map_err(Error::Extraction)?;
-            let a_path_inner = archive_path.join(t.inner_path.clone());
+            let (count, temp_path, inner_path) = (
+                t.strip_components,
+                store.temp_path.clone(),
+                t.inner_path.clone(),
+            );
+            let a_path_inner = tokio::task::spawn_blocking(move || {
+                let archive_path = strip_components(&archive_path, count, &temp_path)?;
+
+                resolve_inner_path(&archive_path, &inner_path)
+            })
+            .await
+            .map_err(|e| Error::Filesystem(e.into()))??;
 
             match fsobj::fs_obj(a_path_inner.clone()).await {
                 fsobj::FsObjectType::Directory => {
-                    match copy_dir::copy_dir(&a_path_inner, &abs_path) {
-                        Ok(_) => {
-                            info!(
-                                "Copied resource directory \"{}\" to \"{}\"",
-                                a_path_inner.to_string_lossy(),
-                                abs_path.to_string_lossy()
-                            );
-                        }
-                        Err(e) => {
-                            debug!("Errors ocurred during JDK copy: {:#?}", e);
-                            return Err(Error::DirectoryCopyFailed(a_path_inner));
-                        }
+                    if !t.include.is_empty() || !t.exclude.is_empty() {
+                        copy_local(store, &a_path_inner, &t.include, &t.exclude, template_path)
+                            .await?;
+
+                        info!(
+                            "Copied selected files from \"{}\" to \"{}\"",
+                            a_path_inner.to_string_lossy(),
+                            abs_path.to_string_lossy()
+                        );
+                    } else if let Err(e) = copy_dir::copy_dir(&a_path_inner, &abs_path) {
+                        debug!("Errors ocurred during JDK copy: {:#?}", e);
+                        return Err(Error::DirectoryCopyFailed(a_path_inner));
+                    } else {
+                        info!(
+                            "Copied resource directory \"{}\" to \"{}\"",
+                            a_path_inner.to_string_lossy(),
+                            abs_path.to_string_lossy()
+                        );
                     }
 
                     for p in &t.post_remove {
-                        let abs_rm_path = abs_path.join(p);
+                        let abs_rm_path =
+                            fsobj::safe_join(&abs_path, p).map_err(Error::UnsafePath)?;
 
                         match fsobj::fs_obj(abs_rm_path.clone()).await {
                             fsobj::FsObjectType::Directory => {
@@ -97,6 +218,11 @@ pub async fn write_remote<P: AsRef<Path>, T: std::fmt::Display>(
                     }
                 }
                 fsobj::FsObjectType::File => {
+                    if !t.include.is_empty() || !t.exclude.is_empty() {
+                        error!("Include and exclude patterns only apply to directories");
+                        return Err(Error::FilesCannotHaveSelection(a_path_inner));
+                    }
+
                     fs::copy(&a_path_inner, &abs_path)
                         .await
                         .map_err(Error::Filesystem)?;
@@ -113,3 +239,42 @@ pub async fn write_remote<P: AsRef<Path>, T: std::fmt::Display>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_and_resolve() {
+        let temp = tempfile::tempdir().unwrap();
+        let extracted = temp.path().join("mypack-1.4.2.zip");
+        let dir = extracted.as_path();
+
+        std::fs::create_dir_all(dir.join("mypack-1.4.2/server/config")).unwrap();
+        std::fs::create_dir_all(dir.join("mypack-1.4.2/client")).unwrap();
+        std::fs::write(dir.join("mypack-1.4.2/server/config/a.toml"), "").unwrap();
+        std::fs::write(dir.join("README.txt"), "").unwrap();
+
+        let stripped = strip_components(dir, 1, temp.path()).unwrap();
+        assert!(stripped.starts_with(temp.path()));
+        assert!(stripped.join("server/config/a.toml").is_file());
+        assert!(!stripped.join("README.txt").exists());
+
+        assert_eq!(
+            resolve_inner_path(dir, Path::new("mypack-*/server")).unwrap(),
+            dir.join("mypack-1.4.2/server")
+        );
+        assert!(matches!(
+            resolve_inner_path(&stripped, Path::new("*")),
+            Err(Error::AmbiguousInnerArchivePath(_, _))
+        ));
+        assert!(matches!(
+            resolve_inner_path(&stripped, Path::new("other-*")),
+            Err(Error::InnerArchivePathNotFound(_))
+        ));
+        assert!(matches!(
+            resolve_inner_path(dir, Path::new("../*")),
+            Err(Error::UnsafePath(_))
+        ));
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::Path;
+use tokio::fs;
+
+use crate::{fsobj, vkstore::VolkanicStore};
+
+use super::Error;
+
+pub async fn write_text<P: AsRef<Path>, T: AsRef<str>>(
+    store: &VolkanicStore,
+    template_path: P,
+    contents: T,
+) -> Result<(), Error> {
+    let abs_path =
+        fsobj::safe_join(&store.build_path, template_path.as_ref()).map_err(Error::UnsafePath)?;
+
+    fsobj::create_ancestors(&abs_path)
+        .await
+        .map_err(Error::CreateFilesystemAncestors)?;
+
+    fs::write(&abs_path, contents.as_ref())
+        .await
+        .map_err(Error::Filesystem)?;
+
+    Ok(())
+}
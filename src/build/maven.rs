@@ -0,0 +1,227 @@
+use reqwest::{Client, StatusCode};
+use tracing::{debug, info};
+
+use crate::netconf;
+
+use super::misc::{self, Verification};
+
+const METADATA_FILE: &str = "maven-metadata.xml";
+
+/// Version resolving to the newest version, including snapshots
+pub const VERSION_LATEST: &str = "LATEST";
+/// Version resolving to the newest release
+pub const VERSION_RELEASE: &str = "RELEASE";
+
+#[derive(Debug, thiserror::Error)]
+pub enum MavenError {
+    #[error("HTTP error: {0}")]
+    Http(reqwest::Error),
+    #[error("Network configuration error: {0}")]
+    NetConfig(netconf::NetConfError),
+    #[error("Not found in repository: {0}")]
+    NotFound(String),
+    #[error("Unable to resolve \"{0}\" version from metadata: {1}")]
+    UnresolvedVersion(String, String),
+    #[error("No SHA-1 or SHA-512 checksum available for: {0}")]
+    NoChecksum(String),
+}
+
+/// Location of an artifact in a Maven repository
+#[derive(Clone, Debug, PartialEq)]
+pub struct Artifact<'a> {
+    pub repository: &'a str,
+    pub group: &'a str,
+    pub artifact: &'a str,
+    pub version: &'a str,
+    pub classifier: Option<&'a str>,
+    pub extension: &'a str,
+}
+
+impl Artifact<'_> {
+    /// URL of the artifact's directory, containing a directory per version
+    fn base_url(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.repository.trim_end_matches('/'),
+            self.group.replace('.', "/"),
+            self.artifact
+        )
+    }
+    /// File name of the artifact
+    ///
+    /// `file_version` differs from the version for timestamped snapshots.
+    pub fn file_name(&self, file_version: &str) -> String {
+        match self.classifier {
+            Some(classifier) => format!(
+                "{}-{}-{}.{}",
+                self.artifact, file_version, classifier, self.extension
+            ),
+            None => format!("{}-{}.{}", self.artifact, file_version, self.extension),
+        }
+    }
+    /// Resolves `LATEST`, `RELEASE` and snapshot versions and returns the URL
+    /// of the artifact file
+    pub async fn resolve_url(&self, client: &Client) -> Result<String, MavenError> {
+        let version = match self.version {
+            VERSION_LATEST | VERSION_RELEASE => {
+                let url = format!("{}/{}", self.base_url(), METADATA_FILE);
+                let metadata = fetch_text(client, &url)
+                    .await?
+                    .ok_or(MavenError::NotFound(url.clone()))?;
+
+                let tag = if self.version == VERSION_LATEST {
+                    "latest"
+                } else {
+                    "release"
+                };
+
+                metadata_value(&metadata, tag)
+                    .ok_or_else(|| MavenError::UnresolvedVersion(self.version.to_string(), url))?
+                    .to_string()
+            }
+            v => v.to_string(),
+        };
+
+        debug!("Resolved version \"{}\" to \"{}\"", self.version, version);
+
+        let version_url = format!("{}/{}", self.base_url(), version);
+
+        // Remote snapshots are stored with a timestamp and build number
+        // instead of "SNAPSHOT" in the file name
+        let mut file_version = version.clone();
+
+        if let Some(base_version) = version.strip_suffix("-SNAPSHOT") {
+            let url = format!("{}/{}", version_url, METADATA_FILE);
+
+            if let Some(metadata) = fetch_text(client, &url).await? {
+                if let (Some(timestamp), Some(build)) = (
+                    metadata_value(&metadata, "timestamp"),
+                    metadata_value(&metadata, "buildNumber"),
+                ) {
+                    file_version = format!("{}-{}-{}", base_version, timestamp, build);
+                }
+            }
+        }
+
+        Ok(format!("{}/{}", version_url, self.file_name(&file_version)))
+    }
+}
+
+/// Fetches a text file, returning `None` if it doesn't exist
+async fn fetch_text(client: &Client, url: &str) -> Result<Option<String>, MavenError> {
+    let response = netconf::get(client, url)
+        .header(
+            reqwest::header::USER_AGENT,
+            misc::default_user_agent().await,
+        )
+        .send()
+        .await
+        .map_err(MavenError::Http)?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        response
+            .error_for_status()
+            .map_err(MavenError::Http)?
+            .text()
+            .await
+            .map_err(MavenError::Http)?,
+    ))
+}
+
+/// Returns the text of the first element with the given tag
+///
+/// Maven metadata is simple enough that a full XML parser isn't necessary.
+fn metadata_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+
+    Some(xml[start..end].trim()).filter(|v| !v.is_empty())
+}
+
+/// Reads the first checksum from a sidecar file (e.g. `.sha1`), which may be
+/// followed by a file name
+fn parse_checksum(contents: &str, len: usize) -> Option<String> {
+    contents
+        .split_whitespace()
+        .next()
+        .filter(|c| c.len() == len && c.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|c| c.to_lowercase())
+}
+
+/// Fetches the SHA-512 sidecar file of an artifact, falling back to SHA-1
+pub async fn checksum(client: &Client, url: &str) -> Result<Verification, MavenError> {
+    if let Some(contents) = fetch_text(client, &format!("{}.sha512", url)).await? {
+        if let Some(sha512) = parse_checksum(&contents, 128) {
+            return Ok(Verification::Sha512(sha512));
+        }
+    }
+
+    if let Some(contents) = fetch_text(client, &format!("{}.sha1", url)).await? {
+        if let Some(sha1) = parse_checksum(&contents, 40) {
+            info!("Only SHA-1 checksum available for \"{}\"", url);
+
+            return Ok(Verification::Sha1(sha1));
+        }
+    }
+
+    Err(MavenError::NoChecksum(url.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let artifact = Artifact {
+            repository: "https://repo.example.com/releases/",
+            group: "net.kyori",
+            artifact: "adventure-api",
+            version: "4.17.0",
+            classifier: Some("sources"),
+            extension: "jar",
+        };
+
+        assert_eq!(
+            artifact.base_url(),
+            "https://repo.example.com/releases/net/kyori/adventure-api"
+        );
+        assert_eq!(
+            artifact.file_name("4.17.0"),
+            "adventure-api-4.17.0-sources.jar"
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <metadata>
+              <versioning>
+                <latest>2.0.0-SNAPSHOT</latest>
+                <release>1.2.0</release>
+                <snapshot>
+                  <timestamp>20240101.120000</timestamp>
+                  <buildNumber>3</buildNumber>
+                </snapshot>
+              </versioning>
+            </metadata>"#;
+
+        assert_eq!(metadata_value(metadata, "latest"), Some("2.0.0-SNAPSHOT"));
+        assert_eq!(metadata_value(metadata, "release"), Some("1.2.0"));
+        assert_eq!(metadata_value(metadata, "buildNumber"), Some("3"));
+        assert_eq!(metadata_value(metadata, "missing"), None);
+
+        assert_eq!(
+            parse_checksum("DA39A3EE5E6B4B0D3255BFEF95601890AFD80709  file.jar\n", 40),
+            Some("da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string())
+        );
+        assert_eq!(parse_checksum("<html>", 40), None);
+    }
+}
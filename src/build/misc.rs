@@ -2,19 +2,30 @@ use flate2::read::GzDecoder;
 use futures_util::stream::StreamExt;
 use indicatif::ProgressBar;
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
 use std::path;
 use tokio::{
     fs,
     io::{self, AsyncReadExt, AsyncWriteExt},
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
+    netconf,
     resources::{self, style, ArchiveFormat},
     vkstore,
 };
 
+use super::archive;
+
+/// Suffix of files which are still being downloaded
+const PART_SUFFIX: &str = ".part";
+/// Suffix of the HTTP validators kept next to unverified and partial
+/// downloads
+const META_SUFFIX: &str = ".meta.json";
+
 #[derive(Debug, thiserror::Error)]
 pub enum DownloadError {
     #[error("HTTP error: {0}")]
@@ -27,15 +38,112 @@ pub enum DownloadError {
     DirectoryAlreadyExists(path::PathBuf),
     #[error("Verification failure: {0}")]
     VerificationFailure(String),
+    #[error("Network configuration error: {0}")]
+    NetConfig(netconf::NetConfError),
+    #[error("Invalid download file name: \"{0}\"")]
+    InvalidName(String),
+    #[error("Hash isn't hexadecimal of the expected length: \"{0}\"")]
+    InvalidHash(String),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Verification {
     None,
+    Sha1(String),
     Sha256(String),
     Sha512(String),
 }
 
+impl Verification {
+    /// Verifications for the given hashes, strongest first
+    pub fn from_hashes(
+        sha512: Option<&String>,
+        sha256: Option<&String>,
+        sha1: Option<&String>,
+    ) -> Vec<Verification> {
+        [
+            sha512.cloned().map(Verification::Sha512),
+            sha256.cloned().map(Verification::Sha256),
+            sha1.cloned().map(Verification::Sha1),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+    /// Checks that the hash is hexadecimal of the right length, as it's used
+    /// as a file name
+    pub fn check(&self) -> Result<(), DownloadError> {
+        let (hash, len) = match self {
+            Verification::None => return Ok(()),
+            Verification::Sha1(h) => (h, 40),
+            Verification::Sha256(h) => (h, 64),
+            Verification::Sha512(h) => (h, 128),
+        };
+
+        if hash.len() != len || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(DownloadError::InvalidHash(hash.to_string()));
+        }
+
+        Ok(())
+    }
+    /// Expected hash, if any
+    pub fn hash(&self) -> Option<&str> {
+        match self {
+            Verification::None => None,
+            Verification::Sha1(h) | Verification::Sha256(h) | Verification::Sha512(h) => Some(h),
+        }
+    }
+}
+
+/// HTTP validators of a download, used to check whether it changed since it
+/// was downloaded or before resuming it
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct CacheMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheMeta {
+    fn from_response(url: &str, response: &reqwest::Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Self {
+            url: url.to_string(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+    /// Validator for `If-Range`, which doesn't allow weak ETags
+    fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|e| !e.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+    async fn read(path: &path::Path) -> Option<Self> {
+        let json = fs::read_to_string(path).await.ok()?;
+
+        serde_jsonc::from_str(&json).ok()
+    }
+}
+
+/// Outcome of a single download attempt
+enum Fetched {
+    Downloaded(CacheMeta),
+    NotModified,
+}
+
 pub async fn get_remote_filename<T: std::fmt::Display>(url: T) -> Option<String> {
     let str = url.to_string();
 
@@ -48,6 +156,17 @@ pub async fn get_remote_filename<T: std::fmt::Display>(url: T) -> Option<String>
     }
 }
 
+/// Checks that a download name is a single file name, so it can't lead
+/// outside of the downloads directory
+pub fn check_file_name(name: &str) -> Result<(), DownloadError> {
+    let mut components = path::Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(path::Component::Normal(n)), None) if n == std::ffi::OsStr::new(name) => Ok(()),
+        _ => Err(DownloadError::InvalidName(name.to_string())),
+    }
+}
+
 pub async fn default_user_agent() -> String {
     format!("8Bitz0/volkanicmc/{}", env!("CARGO_PKG_VERSION"))
 }
@@ -59,8 +178,34 @@ pub async fn download_progress<U: std::fmt::Display, N: std::fmt::Display, A: st
     name: N,
     user_agent: Option<A>,
 ) -> Result<path::PathBuf, DownloadError> {
+    download_file(store, url, verification, name, user_agent, true)
+        .await
+        .map(|(p, _)| p)
+}
+
+/// Downloads a file like [`download_progress`], also returning whether it
+/// was freshly downloaded
+///
+/// Without `reuse`, the shared cache and previous downloads are ignored.
+async fn download_file<U: std::fmt::Display, N: std::fmt::Display, A: std::fmt::Display>(
+    store: vkstore::VolkanicStore,
+    url: U,
+    verification: Verification,
+    name: N,
+    user_agent: Option<A>,
+    reuse: bool,
+) -> Result<(path::PathBuf, bool), DownloadError> {
+    verification.check()?;
+
     let p = store.downloads_path.join(match &verification {
-        Verification::None => format!("noverify-{}", &name),
+        Verification::None => {
+            let name = format!("noverify-{}", &name);
+
+            check_file_name(&name)?;
+
+            name
+        }
+        Verification::Sha1(sha1) => sha1.to_string(),
         Verification::Sha256(sha256) => sha256.to_string(),
         Verification::Sha512(sha512) => sha512.to_string(),
     });
@@ -73,39 +218,309 @@ pub async fn download_progress<U: std::fmt::Display, N: std::fmt::Display, A: st
         warn!("Verification for \"{}\" is unavailable", name);
     }
 
-    // Always re-download if no verification information is provided
-    if p.is_file() && verification != Verification::None {
+    // Unverified files are never shared, as they may differ between stores
+    let cache = match verification {
+        Verification::None => None,
+        _ => store.cache(),
+    };
+
+    if let (true, Some(cache), Some(hash)) = (reuse, &cache, verification.hash()) {
+        match cache.find(hash).await {
+            Ok(Some(entry)) => {
+                let object = cache.object_path(&entry.sha512);
+
+                if verify_hash(object.clone(), &verification).await? {
+                    info!("Using cached \"{}\"", name);
+
+                    if let Err(e) = cache
+                        .touch(entry, &url.to_string(), &store.build_path)
+                        .await
+                    {
+                        warn!("Failed to update download cache: {}", e);
+                    }
+
+                    return Ok((object, false));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to search download cache: {}", e),
+        }
+    }
+
+    if reuse && p.is_file() && verification != Verification::None {
         if verify_hash(p.clone(), &verification).await? {
-            return Ok(p);
+            return Ok((
+                cache_insert(cache.as_ref(), p, &url.to_string(), &store).await,
+                false,
+            ));
         } else {
             warn!("Previously downloaded file for \"{}\" was unable to verify. The file will be re-downloaded.", name);
         }
     }
 
-    info!("Downloading \"{}\"...", name);
+    // Partial downloads are kept next to the final file until verified
+    let part = with_suffix(&p, PART_SUFFIX);
+    let part_meta = with_suffix(&part, META_SUFFIX);
+    let meta_path = with_suffix(&p, META_SUFFIX);
+
+    // Unverified files may change between builds, so they're revalidated
+    // with the server instead of being reused or resumed
+    let cached = if verification == Verification::None {
+        remove_if_file(&part).await?;
+
+        match CacheMeta::read(&meta_path).await {
+            Some(meta) if reuse && p.is_file() && meta.url == url.to_string() => Some(meta),
+            _ => None,
+        }
+    } else {
+        None
+    };
 
-    let client = Client::new();
+    match cached {
+        Some(_) => info!("Checking \"{}\" for changes...", name),
+        None => info!("Downloading \"{}\"...", name),
+    }
+
+    let config = netconf::NetConfig::get().map_err(DownloadError::NetConfig)?;
+    let client = config.client().map_err(DownloadError::Http)?;
 
     let user_agent = match user_agent {
         Some(u) => u.to_string(),
         None => default_user_agent().await,
     };
 
-    let response = client
-        .get(url.to_string())
-        .header(reqwest::header::USER_AGENT, user_agent)
-        .send()
+    let mut retry = 0;
+
+    let meta = loop {
+        match download_part(
+            &client,
+            &url.to_string(),
+            &user_agent,
+            &part,
+            &part_meta,
+            cached.as_ref(),
+        )
         .await
-        .map_err(DownloadError::Http)?;
+        {
+            Ok(Fetched::Downloaded(meta)) => break meta,
+            Ok(Fetched::NotModified) => {
+                info!("\"{}\" is unchanged, using the downloaded copy", name);
+
+                return Ok((p, false));
+            }
+            Err(e) if retry < config.retries && is_retryable(&e) => {
+                retry += 1;
+
+                let delay = config.backoff(retry);
+
+                warn!(
+                    "Download of \"{}\" failed ({}), retrying in {}s ({}/{})...",
+                    name,
+                    e,
+                    delay.as_secs(),
+                    retry,
+                    config.retries
+                );
+
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    if verify_hash(part.clone(), &verification).await? {
+        fs::rename(&part, &p)
+            .await
+            .map_err(DownloadError::Filesystem)?;
+        remove_if_file(&part_meta).await?;
+
+        if verification == Verification::None {
+            write_cache_meta(&meta_path, &meta).await?;
+        }
+
+        Ok((
+            cache_insert(cache.as_ref(), p, &url.to_string(), &store).await,
+            true,
+        ))
+    } else {
+        error!("Downloaded file for \"{}\" was unable to verify. This could be an issue with the template, or somebody is doing something nasty.", name);
+
+        fs::remove_file(&part)
+            .await
+            .map_err(DownloadError::Filesystem)?;
+        remove_if_file(&part_meta).await?;
+
+        Err(DownloadError::VerificationFailure(url.to_string()))
+    }
+}
+
+/// Moves a verified download into the shared cache, returning the path to
+/// use
+///
+/// The cache is an optimization, so the file stays in the store on failure.
+async fn cache_insert(
+    cache: Option<&vkstore::Cache>,
+    p: path::PathBuf,
+    url: &str,
+    store: &vkstore::VolkanicStore,
+) -> path::PathBuf {
+    let Some(cache) = cache else {
+        return p;
+    };
+
+    match cache.insert(&p, url, &store.build_path).await {
+        Ok(object) => object,
+        Err(e) => {
+            warn!("Failed to add \"{}\" to download cache: {}", p.display(), e);
+            p
+        }
+    }
+}
+
+/// Saves the validators of an unverified download, removing stale ones if
+/// the server didn't send any
+async fn write_cache_meta(path: &path::Path, meta: &CacheMeta) -> Result<(), DownloadError> {
+    if meta.is_empty() {
+        return remove_if_file(path).await;
+    }
+
+    let json =
+        serde_jsonc::to_string(meta).map_err(|e| DownloadError::Filesystem(io::Error::other(e)))?;
+
+    fs::write(path, json)
+        .await
+        .map_err(DownloadError::Filesystem)
+}
+
+/// Path of a file next to `p` with a suffix appended to its name
+fn with_suffix(p: &path::Path, suffix: &str) -> path::PathBuf {
+    p.with_file_name(format!(
+        "{}{}",
+        p.file_name().unwrap_or_default().to_string_lossy(),
+        suffix
+    ))
+}
+
+async fn remove_if_file(path: &path::Path) -> Result<(), DownloadError> {
+    if path.is_file() {
+        fs::remove_file(path)
+            .await
+            .map_err(DownloadError::Filesystem)?;
+    }
+
+    Ok(())
+}
+
+/// Whether a failed download is worth retrying
+fn is_retryable(e: &DownloadError) -> bool {
+    match e {
+        DownloadError::Http(e) => match e.status() {
+            Some(status) => {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+        },
+        _ => false,
+    }
+}
+
+/// Downloads into a partial file, resuming from its current size if the
+/// server supports range requests
+///
+/// Resuming requires the validators saved in `part_meta` when the partial
+/// file was started, so a file which changed since is downloaded again from
+/// the beginning. With `cached` validators, the server may instead report
+/// that the previously downloaded file is unchanged.
+async fn download_part(
+    client: &Client,
+    url: &str,
+    user_agent: &str,
+    part: &path::Path,
+    part_meta: &path::Path,
+    cached: Option<&CacheMeta>,
+) -> Result<Fetched, DownloadError> {
+    // Conditional requests may be answered with "304 Not Modified", which
+    // would leave the partial file of an earlier attempt behind
+    if cached.is_some() {
+        remove_if_file(part).await?;
+    }
+
+    let existing = match fs::metadata(part).await {
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    };
+
+    let resume = match existing {
+        0 => None,
+        _ => CacheMeta::read(part_meta)
+            .await
+            .filter(|m| m.url == url && m.if_range().is_some()),
+    };
+
+    let mut request = netconf::get(client, url).header(reqwest::header::USER_AGENT, user_agent);
+
+    if let Some(validator) = resume.as_ref().and_then(CacheMeta::if_range) {
+        debug!("Resuming \"{}\" from byte {}", url, existing);
+
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={}-", existing))
+            .header(reqwest::header::IF_RANGE, validator);
+    }
+
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request.send().await.map_err(DownloadError::Http)?;
+
+    if cached.is_some() && response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+
+    // The partial file already contains the whole file
+    if let Some(meta) = resume.as_ref() {
+        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Fetched::Downloaded(meta.clone()));
+        }
+    }
+
+    let response = response.error_for_status().map_err(DownloadError::Http)?;
+    let meta = CacheMeta::from_response(url, &response);
+
+    // Servers without range support, or with a changed file, send the whole
+    // file again
+    let resumed = resume.is_some() && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    let start = if resumed { existing } else { 0 };
+
+    if !resumed {
+        write_cache_meta(part_meta, &meta).await?;
+    }
 
     let content_length = response.content_length().unwrap_or(0);
-    let pb = ProgressBar::new(content_length);
+    let pb = ProgressBar::new(start + content_length);
     pb.set_style(style::get_pb_style(style::ProgressStyleType::Bytes).await);
-
-    let mut dest = match fs::File::create(&p).await {
+    pb.set_position(start);
+
+    let mut dest = match fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)
+        .await
+    {
         Ok(f) => f,
         Err(e) => {
-            error!("Failed to create file \"{}\": {}", p.to_string_lossy(), e);
+            error!(
+                "Failed to create file \"{}\": {}",
+                part.to_string_lossy(),
+                e
+            );
             return Err(DownloadError::Filesystem(e));
         }
     };
@@ -119,24 +534,110 @@ pub async fn download_progress<U: std::fmt::Display, N: std::fmt::Display, A: st
         match dest.write_all(&chunk).await {
             Ok(_) => {}
             Err(e) => {
-                error!("Failed to write to file \"{}\": {}", p.to_string_lossy(), e);
+                error!(
+                    "Failed to write to file \"{}\": {}",
+                    part.to_string_lossy(),
+                    e
+                );
                 return Err(DownloadError::Filesystem(e));
             }
         }
     }
 
+    dest.flush().await.map_err(DownloadError::Filesystem)?;
+
     pb.finish();
 
-    if p.is_file() {
-        if verify_hash(p.clone(), &verification).await? {
-            return Ok(p);
-        } else {
-            error!("Downloaded file for \"{}\" was unable to verify. This could be an issue with the template, or somebody is doing something nasty.", name);
-            return Err(DownloadError::VerificationFailure(url.to_string()));
+    Ok(Fetched::Downloaded(meta))
+}
+
+/// Downloads a file from the first URL that works, trying the rest in order
+/// as mirrors
+///
+/// The file must match every hash. The first hash is used to identify the
+/// file in the downloads directory, so it should be the strongest.
+pub async fn download_verified<N: std::fmt::Display, A: std::fmt::Display>(
+    store: vkstore::VolkanicStore,
+    urls: &[&str],
+    verifications: &[Verification],
+    name: N,
+    user_agent: Option<A>,
+) -> Result<path::PathBuf, DownloadError> {
+    let (primary, others) = match verifications.split_first() {
+        Some((primary, others)) => (primary.clone(), others),
+        None => (Verification::None, &[][..]),
+    };
+
+    let user_agent = user_agent.map(|u| u.to_string());
+    let mut last_error = DownloadError::VerificationFailure(name.to_string());
+
+    for (i, url) in urls.iter().enumerate() {
+        if i > 0 {
+            info!("Trying mirror \"{}\"", url);
+        }
+
+        // A cached or previous download only matching the primary hash is
+        // downloaded again from the primary URL before trying mirrors
+        let mut reuse = i == 0;
+
+        loop {
+            let (p, fresh) = match download_file(
+                store.clone(),
+                url,
+                primary.clone(),
+                &name,
+                user_agent.as_ref(),
+                reuse,
+            )
+            .await
+            {
+                Ok(d) => d,
+                Err(e) => {
+                    warn!("Download from \"{}\" failed: {}", url, e);
+                    last_error = e;
+                    break;
+                }
+            };
+
+            let mut verified = true;
+
+            for v in others {
+                if !verify_hash(p.clone(), v).await? {
+                    verified = false;
+                    break;
+                }
+            }
+
+            if verified {
+                return Ok(p);
+            }
+
+            if !fresh {
+                warn!(
+                    "Previously downloaded file for \"{}\" doesn't match all hashes. The file will be re-downloaded.",
+                    name
+                );
+
+                reuse = false;
+                continue;
+            }
+
+            error!("File from \"{}\" doesn't match all hashes", url);
+
+            // Cached files are shared with other builds and match the primary
+            // hash, so only this store's own copy is removed
+            if p.starts_with(&store.downloads_path) {
+                fs::remove_file(&p)
+                    .await
+                    .map_err(DownloadError::Filesystem)?;
+            }
+
+            last_error = DownloadError::VerificationFailure(url.to_string());
+            break;
         }
     }
 
-    Ok(p)
+    Err(last_error)
 }
 
 pub async fn verify_hash(
@@ -159,47 +660,35 @@ pub async fn verify_hash(
         }
     };
 
-    let mut buffer = [0; resources::conf::FILE_BUFFER_SIZE];
-
-    match verification {
-        Verification::Sha256(checksum) => {
-            let mut hasher = Sha256::new();
+    let (digest, checksum) = match verification {
+        Verification::Sha1(checksum) => (file_digest::<Sha1>(&mut file).await?, checksum),
+        Verification::Sha256(checksum) => (file_digest::<Sha256>(&mut file).await?, checksum),
+        Verification::Sha512(checksum) => (file_digest::<Sha512>(&mut file).await?, checksum),
+        Verification::None => return Ok(true),
+    };
 
-            loop {
-                let bytes_read = file
-                    .read(&mut buffer)
-                    .await
-                    .map_err(DownloadError::Filesystem)?;
+    Ok(digest == hex::decode(checksum).map_err(DownloadError::Hex)?)
+}
 
-                if bytes_read == 0 {
-                    break;
-                }
+/// Hashes the rest of a file with the given digest algorithm
+async fn file_digest<D: Digest>(file: &mut fs::File) -> Result<Vec<u8>, DownloadError> {
+    let mut hasher = D::new();
+    let mut buffer = [0; resources::conf::FILE_BUFFER_SIZE];
 
-                hasher.update(&buffer[..bytes_read]);
-            }
+    loop {
+        let bytes_read = file
+            .read(&mut buffer)
+            .await
+            .map_err(DownloadError::Filesystem)?;
 
-            Ok(hasher.finalize()[..] == hex::decode(checksum).map_err(DownloadError::Hex)?)
+        if bytes_read == 0 {
+            break;
         }
-        Verification::Sha512(checksum) => {
-            let mut hasher = Sha512::new();
-
-            loop {
-                let bytes_read = file
-                    .read(&mut buffer)
-                    .await
-                    .map_err(DownloadError::Filesystem)?;
-
-                if bytes_read == 0 {
-                    break;
-                }
-
-                hasher.update(&buffer[..bytes_read]);
-            }
 
-            Ok(hasher.finalize()[..] == hex::decode(checksum).map_err(DownloadError::Hex)?)
-        }
-        Verification::None => Ok(true),
+        hasher.update(&buffer[..bytes_read]);
     }
+
+    Ok(hasher.finalize().to_vec())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -208,16 +697,29 @@ pub enum ExtractionError {
     FilesystemError(io::Error),
     #[error("No file name for path: {0}")]
     NoFileName(path::PathBuf),
-    #[error("Tar archive error: {0}")]
-    TarError(std::io::Error),
-    #[error("Zip archive error: {0}")]
-    ZipError(zip::result::ZipError),
+    #[error("Archive error: {0}")]
+    Archive(archive::ArchiveError),
+    #[error("Unknown archive format: {0}")]
+    UnknownFormat(path::PathBuf),
+}
+
+/// Detects an archive's format from its magic bytes
+fn detect_format(path: &path::Path) -> Result<Option<ArchiveFormat>, io::Error> {
+    use std::io::Read;
+
+    let mut header = Vec::with_capacity(ArchiveFormat::MAGIC_LEN);
+
+    std::fs::File::open(path)?
+        .take(ArchiveFormat::MAGIC_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    Ok(ArchiveFormat::detect(&header))
 }
 
 pub async fn extract(
     store: vkstore::VolkanicStore,
     orig_path: path::PathBuf,
-    format: ArchiveFormat,
+    format: Option<ArchiveFormat>,
 ) -> Result<path::PathBuf, ExtractionError> {
     let new_path = store.temp_path.join(match orig_path.file_name() {
         Some(file_name) => file_name,
@@ -230,37 +732,241 @@ pub async fn extract(
             .map_err(ExtractionError::FilesystemError)?;
     }
 
+    let format = match format {
+        Some(format) => format,
+        None => match detect_format(&orig_path).map_err(ExtractionError::FilesystemError)? {
+            Some(format) => {
+                debug!(
+                    "Detected \"{}\" as {:?}",
+                    orig_path.to_string_lossy(),
+                    format
+                );
+                format
+            }
+            None => return Err(ExtractionError::UnknownFormat(orig_path)),
+        },
+    };
+
     info!("Extracting \"{}\"...", orig_path.to_string_lossy());
 
-    match format {
-        ArchiveFormat::TarGz => {
-            let f = std::fs::File::open(&orig_path).map_err(ExtractionError::FilesystemError)?;
+    let f = std::fs::File::open(&orig_path).map_err(ExtractionError::FilesystemError)?;
+    let dest = new_path.clone();
+    let limits = archive::Limits::default();
+
+    // Extraction is blocking, and archives may be large
+    tokio::task::spawn_blocking(move || match format {
+        ArchiveFormat::TarGz => archive::unpack_tar(GzDecoder::new(f), &dest, limits),
+        ArchiveFormat::TarXz => archive::unpack_tar(xz2::read::XzDecoder::new(f), &dest, limits),
+        ArchiveFormat::TarZst => archive::unpack_tar(
+            zstd::stream::read::Decoder::new(f).map_err(archive::ArchiveError::Filesystem)?,
+            &dest,
+            limits,
+        ),
+        ArchiveFormat::TarBz2 => archive::unpack_tar(bzip2::read::BzDecoder::new(f), &dest, limits),
+        ArchiveFormat::Tar => archive::unpack_tar(f, &dest, limits),
+        ArchiveFormat::Zip | ArchiveFormat::Jar => archive::unpack_zip(f, &dest, limits),
+    })
+    .await
+    .map_err(|e| ExtractionError::FilesystemError(e.into()))?
+    .map_err(ExtractionError::Archive)?;
 
-            let gz_decoder = GzDecoder::new(f);
-            let mut archive = tar::Archive::new(gz_decoder);
+    Ok(new_path)
+}
 
-            fs::create_dir_all(&new_path)
-                .await
-                .map_err(ExtractionError::FilesystemError)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
 
-            archive
-                .unpack(&new_path)
-                .map_err(ExtractionError::TarError)?;
-        }
-        ArchiveFormat::Zip => {
-            let f = std::fs::File::open(&orig_path).map_err(ExtractionError::FilesystemError)?;
+    /// Serves one canned response per connection, returning the URL and
+    /// the received request heads
+    async fn serve(responses: Vec<&'static str>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
 
-            let mut archive = zip::ZipArchive::new(f).map_err(ExtractionError::ZipError)?;
+        tokio::spawn(async move {
+            for response in responses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut head = String::new();
 
-            fs::create_dir_all(&new_path)
-                .await
-                .map_err(ExtractionError::FilesystemError)?;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+
+                    if line.trim().is_empty() {
+                        break;
+                    }
 
-            archive
-                .extract(&new_path)
-                .map_err(ExtractionError::ZipError)?;
+                    head.push_str(&line.to_lowercase());
+                }
+
+                let _ = tx.send(head);
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    async fn fetch(
+        url: &str,
+        part: &path::Path,
+        cached: Option<&CacheMeta>,
+    ) -> Result<Fetched, DownloadError> {
+        download_part(
+            &Client::new(),
+            url,
+            "test",
+            part,
+            &with_suffix(part, META_SUFFIX),
+            cached,
+        )
+        .await
+    }
+
+    fn write_meta(path: &path::Path, url: &str, etag: &str) {
+        let meta = CacheMeta {
+            url: url.to_string(),
+            etag: Some(etag.to_string()),
+            last_modified: None,
+        };
+
+        std::fs::write(path, serde_jsonc::to_string(&meta).unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_download_names() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = vkstore::VolkanicStore::new_custom_root(temp.path().join("store")).await;
+        let url = "http://127.0.0.1:9/file";
+
+        assert!(check_file_name("server.jar").is_ok());
+        for name in ["", ".", "..", "../x", "a/b", "/etc/passwd", "a/"] {
+            assert!(
+                matches!(check_file_name(name), Err(DownloadError::InvalidName(_))),
+                "{}",
+                name
+            );
         }
+
+        assert!(Verification::Sha1("a".repeat(40)).check().is_ok());
+        for verification in [
+            Verification::Sha1("a".repeat(64)),
+            Verification::Sha256("../../../x".into()),
+            Verification::Sha512(format!("../{}", "a".repeat(125))),
+        ] {
+            assert!(matches!(
+                download_progress(store.clone(), url, verification, "x", None::<String>).await,
+                Err(DownloadError::InvalidHash(_))
+            ));
+        }
+
+        assert!(matches!(
+            download_progress(
+                store.clone(),
+                url,
+                Verification::None,
+                "../../../x",
+                None::<String>
+            )
+            .await,
+            Err(DownloadError::InvalidName(_))
+        ));
+        assert!(!temp.path().join("x.part").exists());
+        assert!(!temp.path().join("store/x.part").exists());
     }
 
-    Ok(new_path)
+    #[tokio::test]
+    async fn test_resume_if_range() {
+        let temp = tempfile::tempdir().unwrap();
+        let part = temp.path().join("file.part");
+
+        let (url, mut heads) = serve(vec![
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 6-10/11\r\nContent-Length: 5\r\nConnection: close\r\n\r\nworld",
+            "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nContent-Length: 11\r\nConnection: close\r\n\r\nHELLO WORLD",
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nnew",
+        ])
+        .await;
+
+        // Resumed with the validator saved when the file was started
+        std::fs::write(&part, "hello ").unwrap();
+        write_meta(&with_suffix(&part, META_SUFFIX), &url, "\"v1\"");
+
+        fetch(&url, &part, None).await.unwrap();
+
+        let head = heads.recv().await.unwrap();
+        assert!(head.contains("range: bytes=6-"));
+        assert!(head.contains("if-range: \"v1\""));
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "hello world");
+
+        // The file changed, so the server sent all of it
+        fetch(&url, &part, None).await.unwrap();
+
+        assert!(heads.recv().await.unwrap().contains("if-range: \"v1\""));
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "HELLO WORLD");
+        assert_eq!(
+            CacheMeta::read(&with_suffix(&part, META_SUFFIX))
+                .await
+                .unwrap()
+                .etag
+                .as_deref(),
+            Some("\"v2\"")
+        );
+
+        // Without validators, the partial file can't be resumed safely
+        std::fs::remove_file(with_suffix(&part, META_SUFFIX)).unwrap();
+        fetch(&url, &part, None).await.unwrap();
+
+        assert!(!heads.recv().await.unwrap().contains("range:"));
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_request() {
+        let temp = tempfile::tempdir().unwrap();
+        let part = temp.path().join("file.part");
+
+        let (url, mut heads) = serve(vec![
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nETag: \"v2\"\r\nLast-Modified: Sat, 17 Oct 2026 00:00:00 GMT\r\nContent-Length: 7\r\nConnection: close\r\n\r\nchanged",
+        ])
+        .await;
+
+        let cached = CacheMeta {
+            url: url.clone(),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        };
+
+        // Left behind by an earlier attempt which failed partway
+        std::fs::write(&part, "parti").unwrap();
+        write_meta(&with_suffix(&part, META_SUFFIX), &url, "\"v1\"");
+
+        let fetched = fetch(&url, &part, Some(&cached)).await.unwrap();
+
+        let head = heads.recv().await.unwrap();
+        assert!(head.contains("if-none-match: \"v1\""));
+        assert!(!head.contains("range:"));
+        assert!(matches!(fetched, Fetched::NotModified));
+        assert!(!part.exists());
+
+        let Fetched::Downloaded(meta) = fetch(&url, &part, Some(&cached)).await.unwrap() else {
+            panic!("changed file wasn't downloaded");
+        };
+
+        assert_eq!(meta.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(
+            meta.last_modified.as_deref(),
+            Some("Sat, 17 Oct 2026 00:00:00 GMT")
+        );
+        assert_eq!(std::fs::read_to_string(&part).unwrap(), "changed");
+    }
 }
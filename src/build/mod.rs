@@ -1,12 +1,17 @@
 use tracing::{debug, error, info, warn};
 
+pub mod archive;
 mod buildinfo;
+mod git;
 mod job;
+mod maven;
 mod misc;
+mod patch;
 mod prepare_jdk;
 
 use crate::exec;
 use crate::hostinfo;
+use crate::rcon;
 use crate::resources::{self, JdkLookup};
 use crate::template::{self, overlay::Overlay};
 use crate::vkstore;
@@ -37,6 +42,7 @@ pub async fn build(
     store: vkstore::VolkanicStore,
     force: bool,
     user_vars_raw: Vec<String>,
+    include_dir: Option<std::path::PathBuf>,
     additional_jvm_args: Vec<String>,
     prevent_verify: bool,
     force_jdk_distribution: Option<String>,
@@ -68,18 +74,33 @@ pub async fn build(
     let jdk_config = JdkLookup::new();
 
     info!("Creating template variables...");
-    let mut variables = template::var::VarMap::new();
+    let mut variables = template::var::builtin::builtins(&template).await;
 
     template::var::process_vars(&mut variables, template.variables.clone(), &user_vars)
         .await
         .map_err(BuildError::VarProcess)?;
 
+    let mut template = template;
+    let mut overlays = overlays;
+
+    template
+        .substitute_vars(&variables)
+        .await
+        .map_err(BuildError::VarProcess)?;
+
+    for o in &mut overlays {
+        o.substitute_vars(&variables)
+            .await
+            .map_err(BuildError::VarProcess)?;
+    }
+
     info!("Creating jobs...");
     let jobs = job::create_jobs(
         &template,
         &overlays,
         jdk_config,
         &variables,
+        include_dir.as_deref(),
         prevent_verify,
         force_jdk_distribution,
         preferred_distributions,
@@ -141,33 +162,42 @@ pub async fn build(
             jar_path,
             jdk_args,
             server_args,
-        } => exec::BuildExecInfo {
-            arch: if let Some(a) = hostinfo::Arch::get().await {
-                a
-            } else {
-                return Err(BuildError::UnknownArchitecture);
-            },
-            os: if let Some(a) = hostinfo::Os::get().await {
-                a
-            } else {
-                return Err(BuildError::UnknownPlatform);
-            },
-            exec_path: store.runtime_path.join(resources::conf::JDK_BIN_FILE),
-            args: {
-                let mut args: Vec<String> = vec![];
-
-                args.extend(jdk_args);
-                args.extend(additional_jvm_args);
-
-                args.push("-jar".to_string());
-                args.push(jar_path.to_string_lossy().to_string());
-                args.push(server_args.join(" "));
-
-                args
-            },
-        },
+            stop_command,
+        } => {
+            let mut args: Vec<String> = vec![];
+
+            args.extend(jdk_args);
+            args.extend(additional_jvm_args);
+
+            args.push("-jar".to_string());
+            args.push(jar_path.to_string_lossy().to_string());
+            args.push(server_args.join(" "));
+
+            exec::BuildExecInfo {
+                arch: if let Some(a) = hostinfo::Arch::get().await {
+                    a
+                } else {
+                    return Err(BuildError::UnknownArchitecture);
+                },
+                os: if let Some(a) = hostinfo::Os::get().await {
+                    a
+                } else {
+                    return Err(BuildError::UnknownPlatform);
+                },
+                exec_path: store.runtime_path.join(resources::conf::JDK_BIN_FILE),
+                limits: exec::derive_limits(template.limits.as_ref(), &args),
+                args,
+                stop_command,
+            }
+        }
     });
 
+    // Other variables may hold secrets, so only what RCON needs is kept
+    build_info.variables = variables
+        .into_iter()
+        .filter(|(k, _)| k == rcon::PORT_VAR || k == rcon::PASSWORD_VAR)
+        .collect();
+
     build_info.update().await.map_err(BuildError::BuildInfo)?;
 
     info!("Build complete");
@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+
+use crate::template::resource::PatchFormat;
+
+const KEY_SEPARATOR: char = '.';
+
+#[derive(Debug, thiserror::Error)]
+pub enum PatchError {
+    #[error("Failed to parse JSON: {0}")]
+    JsonParse(serde_jsonc::Error),
+    #[error("Failed to serialize JSON: {0}")]
+    JsonSerialize(serde_jsonc::Error),
+    #[error("YAML error: {0}")]
+    Yaml(serde_yaml::Error),
+    #[error("Failed to parse TOML: {0}")]
+    TomlParse(toml_edit::TomlError),
+    #[error("Value cannot be represented in TOML: {0}")]
+    UnsupportedTomlValue(serde_jsonc::Value),
+    #[error("Expected a table or mapping at key: {0}")]
+    NotAMapping(String),
+}
+
+/// Sets and removes keys in the contents of a structured file
+///
+/// Nested keys are separated with dots, except in `.properties` files where
+/// keys are used as-is. Comments are kept for `.properties` and TOML files.
+pub fn patch(
+    contents: &str,
+    format: &PatchFormat,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> Result<String, PatchError> {
+    match format {
+        PatchFormat::Properties => Ok(patch_properties(contents, set, remove)),
+        PatchFormat::Json => patch_json(contents, set, remove),
+        PatchFormat::Yaml => patch_yaml(contents, set, remove),
+        PatchFormat::Toml => patch_toml(contents, set, remove),
+    }
+}
+
+/// Returns the key of a `.properties` line, or `None` for blank lines and comments
+fn properties_key(line: &str) -> Option<String> {
+    let line = line.trim_start();
+
+    if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+        return None;
+    }
+
+    let mut key = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    key.push(escaped);
+                }
+            }
+            '=' | ':' => break,
+            c if c.is_whitespace() => break,
+            c => key.push(c),
+        }
+    }
+
+    Some(key)
+}
+
+/// Whether a `.properties` line continues onto the next line
+fn properties_continues(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+fn properties_escape(value: &str, is_key: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '=' | ':' | '#' | '!' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' ' if is_key || i == 0 => escaped.push_str("\\ "),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn properties_value(value: &serde_jsonc::Value) -> String {
+    match value {
+        serde_jsonc::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn patch_properties(
+    contents: &str,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> String {
+    let newline = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+
+    let mut lines: Vec<String> = vec![];
+    let mut written = vec![];
+
+    let mut source = contents.lines();
+
+    while let Some(line) = source.next() {
+        // Gather continuation lines so the whole entry can be replaced
+        let mut entry = vec![line];
+        let mut last = line;
+
+        while properties_continues(last) {
+            match source.next() {
+                Some(next) => {
+                    entry.push(next);
+                    last = next;
+                }
+                None => break,
+            }
+        }
+
+        match properties_key(line) {
+            Some(key) if remove.contains(&key) => {}
+            Some(key) if set.contains_key(&key) => {
+                if !written.contains(&key) {
+                    lines.push(format!(
+                        "{}={}",
+                        properties_escape(&key, true),
+                        properties_escape(&properties_value(&set[&key]), false)
+                    ));
+                    written.push(key);
+                }
+            }
+            _ => lines.extend(entry.iter().map(|l| l.to_string())),
+        }
+    }
+
+    for (key, value) in set {
+        if !written.contains(key) {
+            lines.push(format!(
+                "{}={}",
+                properties_escape(key, true),
+                properties_escape(&properties_value(value), false)
+            ));
+        }
+    }
+
+    let mut result = lines.join(newline);
+    result.push_str(newline);
+
+    result
+}
+
+fn patch_json(
+    contents: &str,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> Result<String, PatchError> {
+    let mut root: serde_jsonc::Value = if contents.trim().is_empty() {
+        serde_jsonc::Value::Object(Default::default())
+    } else {
+        serde_jsonc::from_str(contents).map_err(PatchError::JsonParse)?
+    };
+
+    for key in remove {
+        let (parents, last) = split_key(key);
+
+        let mut current = Some(&mut root);
+        for p in parents {
+            current = current.and_then(|c| c.get_mut(p));
+        }
+
+        if let Some(serde_jsonc::Value::Object(map)) = current {
+            map.remove(last);
+        }
+    }
+
+    for (key, value) in set {
+        let (parents, last) = split_key(key);
+
+        let mut current = &mut root;
+        for p in parents {
+            current = match current {
+                serde_jsonc::Value::Object(map) => map
+                    .entry(p.to_string())
+                    .or_insert_with(|| serde_jsonc::Value::Object(Default::default())),
+                _ => return Err(PatchError::NotAMapping(key.clone())),
+            };
+        }
+
+        match current {
+            serde_jsonc::Value::Object(map) => {
+                map.insert(last.to_string(), value.clone());
+            }
+            _ => return Err(PatchError::NotAMapping(key.clone())),
+        }
+    }
+
+    let mut result = serde_jsonc::to_string_pretty(&root).map_err(PatchError::JsonSerialize)?;
+    result.push('\n');
+
+    Ok(result)
+}
+
+fn patch_yaml(
+    contents: &str,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> Result<String, PatchError> {
+    let mut root: serde_yaml::Value = if contents.trim().is_empty() {
+        serde_yaml::Value::Mapping(Default::default())
+    } else {
+        serde_yaml::from_str(contents).map_err(PatchError::Yaml)?
+    };
+
+    for key in remove {
+        let (parents, last) = split_key(key);
+
+        let mut current = Some(&mut root);
+        for p in parents {
+            current = current.and_then(|c| c.get_mut(p));
+        }
+
+        if let Some(serde_yaml::Value::Mapping(map)) = current {
+            map.remove(last);
+        }
+    }
+
+    for (key, value) in set {
+        let (parents, last) = split_key(key);
+
+        let mut current = &mut root;
+        for p in parents {
+            current = match current {
+                serde_yaml::Value::Mapping(map) => map
+                    .entry(p.into())
+                    .or_insert_with(|| serde_yaml::Value::Mapping(Default::default())),
+                _ => return Err(PatchError::NotAMapping(key.clone())),
+            };
+        }
+
+        match current {
+            serde_yaml::Value::Mapping(map) => {
+                map.insert(
+                    last.into(),
+                    serde_yaml::to_value(value).map_err(PatchError::Yaml)?,
+                );
+            }
+            _ => return Err(PatchError::NotAMapping(key.clone())),
+        }
+    }
+
+    serde_yaml::to_string(&root).map_err(PatchError::Yaml)
+}
+
+fn toml_value(value: &serde_jsonc::Value) -> Result<toml_edit::Value, PatchError> {
+    Ok(match value {
+        serde_jsonc::Value::Bool(b) => (*b).into(),
+        serde_jsonc::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => i.into(),
+            (None, Some(f)) => f.into(),
+            _ => return Err(PatchError::UnsupportedTomlValue(value.clone())),
+        },
+        serde_jsonc::Value::String(s) => s.as_str().into(),
+        serde_jsonc::Value::Array(a) => {
+            let mut array = toml_edit::Array::new();
+
+            for v in a {
+                array.push(toml_value(v)?);
+            }
+
+            array.into()
+        }
+        serde_jsonc::Value::Object(o) => {
+            let mut table = toml_edit::InlineTable::new();
+
+            for (k, v) in o {
+                table.insert(k, toml_value(v)?);
+            }
+
+            table.into()
+        }
+        serde_jsonc::Value::Null => return Err(PatchError::UnsupportedTomlValue(value.clone())),
+    })
+}
+
+fn patch_toml(
+    contents: &str,
+    set: &BTreeMap<String, serde_jsonc::Value>,
+    remove: &[String],
+) -> Result<String, PatchError> {
+    let mut doc = contents
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(PatchError::TomlParse)?;
+
+    for key in remove {
+        let (parents, last) = split_key(key);
+
+        let mut current = Some(doc.as_table_mut() as &mut dyn toml_edit::TableLike);
+        for p in parents {
+            current = current
+                .and_then(|c| c.get_mut(p))
+                .and_then(|i| i.as_table_like_mut());
+        }
+
+        if let Some(table) = current {
+            table.remove(last);
+        }
+    }
+
+    for (key, value) in set {
+        let (parents, last) = split_key(key);
+
+        let mut current = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+        for p in parents {
+            current = current
+                .entry(p)
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| PatchError::NotAMapping(key.clone()))?;
+        }
+
+        current.insert(last, toml_edit::Item::Value(toml_value(value)?));
+    }
+
+    Ok(doc.to_string())
+}
+
+/// Splits a dotted key into its parent keys and the final key
+fn split_key(key: &str) -> (Vec<&str>, &str) {
+    let mut parts: Vec<&str> = key.split(KEY_SEPARATOR).collect();
+
+    // `unwrap()` is safe as `split()` always yields at least one part
+    let last = parts.pop().unwrap();
+
+    (parts, last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(pairs: &[(&str, serde_jsonc::Value)]) -> BTreeMap<String, serde_jsonc::Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_properties() {
+        let contents = "#Minecraft server properties\nmotd=A Minecraft Server\nlevel-type=minecraft\\:normal\nwhite-list=false\nspawn-protection=16\n";
+
+        let result = patch(
+            contents,
+            &PatchFormat::Properties,
+            &set(&[("motd", "Hello: world".into()), ("max-players", 8.into())]),
+            &["spawn-protection".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            "#Minecraft server properties\nmotd=Hello\\: world\nlevel-type=minecraft\\:normal\nwhite-list=false\nmax-players=8\n"
+        );
+    }
+
+    #[test]
+    fn test_yaml() {
+        let contents = "settings:\n  debug: false\n  connection-throttle: 4000\naliases: now-in-commands.yml\n";
+
+        let result = patch(
+            contents,
+            &PatchFormat::Yaml,
+            &set(&[("settings.debug", true.into()), ("new.key", "x".into())]),
+            &["aliases".to_string()],
+        )
+        .unwrap();
+
+        let value: serde_yaml::Value = serde_yaml::from_str(&result).unwrap();
+
+        assert_eq!(value["settings"]["debug"], serde_yaml::Value::Bool(true));
+        assert_eq!(
+            value["settings"]["connection-throttle"],
+            serde_yaml::Value::from(4000)
+        );
+        assert_eq!(value["new"]["key"], serde_yaml::Value::from("x"));
+        assert!(value.get("aliases").is_none());
+    }
+
+    #[test]
+    fn test_json() {
+        let result = patch(
+            "{\"a\": {\"b\": 1, \"c\": 2}}",
+            &PatchFormat::Json,
+            &set(&[("a.b", 3.into())]),
+            &["a.c".to_string()],
+        )
+        .unwrap();
+
+        let value: serde_jsonc::Value = serde_jsonc::from_str(&result).unwrap();
+
+        assert_eq!(
+            value,
+            serde_jsonc::from_str::<serde_jsonc::Value>("{\"a\": {\"b\": 3}}").unwrap()
+        );
+
+        assert!(matches!(
+            patch(
+                "{\"a\": 1}",
+                &PatchFormat::Json,
+                &set(&[("a.b", 3.into())]),
+                &[]
+            ),
+            Err(PatchError::NotAMapping(_))
+        ));
+    }
+
+    #[test]
+    fn test_toml() {
+        let contents =
+            "# Server settings\n[server]\nport = 25565 # default port\nremove-me = true\n";
+
+        let result = patch(
+            contents,
+            &PatchFormat::Toml,
+            &set(&[("server.port", 25566.into()), ("other.name", "x".into())]),
+            &["server.remove-me".to_string()],
+        )
+        .unwrap();
+
+        assert!(result.contains("# Server settings"));
+        assert!(result.contains("port = 25566"));
+        assert!(!result.contains("remove-me"));
+        assert!(result.contains("[other]\nname = \"x\""));
+    }
+}
@@ -158,7 +158,7 @@ pub async fn prepare_jdk(
     .await
     .map_err(Error::Download)?;
 
-    let ex_path = extract(store.clone(), jdk_path, jdk.format)
+    let ex_path = extract(store.clone(), jdk_path, Some(jdk.format))
         .await
         .map_err(Error::Extraction)?;
 
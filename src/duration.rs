@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid duration: {0}")]
+pub struct InvalidDurationError(pub String);
+
+/// Parses a duration with a unit (e.g. `12h`, `30d`, `2w`)
+pub fn parse_duration(duration: &str) -> Result<Duration, InvalidDurationError> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split);
+
+    let multiplier: u64 = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => return Err(InvalidDurationError(duration.to_string())),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| InvalidDurationError(duration.to_string()))
+}
+
+/// Formats a duration in its largest whole unit
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    match secs {
+        s if s >= 60 * 60 * 24 => format!("{}d", s / (60 * 60 * 24)),
+        s if s >= 60 * 60 => format!("{}h", s / (60 * 60)),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(
+            parse_duration("30d").unwrap(),
+            Duration::from_secs(2_592_000)
+        );
+        assert_eq!(parse_duration("10 s").unwrap(), Duration::from_secs(10));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("m").is_err());
+        assert_eq!(format_duration(Duration::from_secs(90_000)), "1d");
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+    }
+}
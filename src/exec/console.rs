@@ -0,0 +1,517 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio::io::AsyncWriteExt;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::sync::mpsc;
+#[cfg(unix)]
+use tracing::warn;
+
+use crate::vkstore::VolkanicStore;
+
+const CONSOLE_SOCKET: &str = "console.sock";
+/// Lines of server output replayed to clients when they attach
+const SCROLLBACK_LINES: usize = 1000;
+/// Prefix for messages from vkconstruct rather than the server
+#[cfg(unix)]
+const MESSAGE_PREFIX: &str = "[vkconstruct]";
+
+/// Path of the console socket for a store
+pub fn socket_path(store: &VolkanicStore) -> PathBuf {
+    store.path.join(CONSOLE_SOCKET)
+}
+
+/// Mode requested by a client in its first line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Read,
+    Write,
+}
+
+#[cfg(unix)]
+impl Mode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Read => "read",
+            Mode::Write => "write",
+        }
+    }
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "read" => Some(Mode::Read),
+            "write" => Some(Mode::Write),
+            _ => None,
+        }
+    }
+}
+
+/// Reads lines, replacing invalid UTF-8 instead of failing on it, so
+/// servers and clients printing other encodings are still read
+pub struct LossyLines<R> {
+    reader: BufReader<R>,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> LossyLines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            buf: vec![],
+        }
+    }
+    /// Returns the next line without its line ending, or `None` at the end
+    /// of the input
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        self.buf.clear();
+
+        if self.reader.read_until(b'\n', &mut self.buf).await? == 0 {
+            return Ok(None);
+        }
+
+        let line = self.buf.strip_suffix(b"\n").unwrap_or(&self.buf);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        Ok(Some(String::from_utf8_lossy(line).into_owned()))
+    }
+}
+
+/// Server output shared between the terminal and attached clients
+#[derive(Debug)]
+pub struct Console {
+    scrollback: Mutex<VecDeque<String>>,
+    output: broadcast::Sender<String>,
+    /// Whether a client currently holds the writer role
+    #[cfg(unix)]
+    writer: Mutex<bool>,
+}
+
+impl Console {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            scrollback: Mutex::new(VecDeque::with_capacity(SCROLLBACK_LINES)),
+            output: broadcast::channel(SCROLLBACK_LINES).0,
+            #[cfg(unix)]
+            writer: Mutex::new(false),
+        })
+    }
+    /// Records a line of server output and sends it to attached clients
+    fn push(&self, line: String) {
+        let mut scrollback = self.scrollback.lock().unwrap();
+
+        if scrollback.len() == SCROLLBACK_LINES {
+            scrollback.pop_front();
+        }
+
+        scrollback.push_back(line.clone());
+
+        // Holding the scrollback lock keeps new clients from missing lines
+        // between their replay and subscription
+        let _ = self.output.send(line);
+    }
+    /// Copies server output to our own stdout or stderr and the console
+    pub fn pipe<R: AsyncRead + Unpin + Send + 'static>(
+        self: &Arc<Self>,
+        reader: R,
+        stderr: bool,
+    ) -> tokio::task::JoinHandle<()> {
+        let console = self.clone();
+
+        tokio::spawn(async move {
+            let mut lines = LossyLines::new(reader);
+
+            // The server blocks once the pipe is full, so keep reading until
+            // it's closed
+            loop {
+                let line = match lines.next_line().await {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Failed to read server output: {}", e);
+                        continue;
+                    }
+                };
+
+                if stderr {
+                    let _ = writeln!(std::io::stderr(), "{}", line);
+                } else {
+                    let _ = writeln!(std::io::stdout(), "{}", line);
+                }
+
+                console.push(line);
+            }
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Console {
+    fn subscribe(&self) -> (Vec<String>, broadcast::Receiver<String>) {
+        let scrollback = self.scrollback.lock().unwrap();
+
+        (
+            scrollback.iter().cloned().collect(),
+            self.output.subscribe(),
+        )
+    }
+    /// Claims the writer role, returning `false` if another client has it
+    fn claim_writer(&self) -> bool {
+        let mut writer = self.writer.lock().unwrap();
+
+        !std::mem::replace(&mut *writer, true)
+    }
+    fn release_writer(&self) {
+        *self.writer.lock().unwrap() = false;
+    }
+}
+
+/// Removes the console socket when the server stops
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Listener {
+    path: PathBuf,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("Failed to remove console socket: {}", e);
+        }
+    }
+}
+
+/// Errors from binding the console socket
+#[derive(Debug, thiserror::Error)]
+pub enum ConsoleError {
+    #[error("Console socket error: {0}")]
+    Socket(io::Error),
+    #[error("Another server is using the console socket: {0}")]
+    InUse(PathBuf),
+    #[cfg(not(unix))]
+    #[error("Console attach isn't supported on this platform")]
+    Unsupported,
+}
+
+/// Exposes the console on a Unix socket. Lines written by the client holding
+/// the writer role are sent to `input`.
+#[cfg(unix)]
+pub async fn listen(
+    console: &Arc<Console>,
+    path: &Path,
+    input: mpsc::Sender<String>,
+) -> Result<Listener, ConsoleError> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(ConsoleError::InUse(path.to_path_buf()));
+        }
+
+        debug!("Removing stale console socket");
+        std::fs::remove_file(path).map_err(ConsoleError::Socket)?;
+    }
+
+    let listener = bind_private(path).map_err(ConsoleError::Socket)?;
+
+    let console = console.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(console.clone(), stream, input.clone()));
+                }
+                Err(e) => {
+                    warn!("Failed to accept console client: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(Listener {
+        path: path.to_path_buf(),
+        task,
+    })
+}
+
+/// Binds a socket only the owner can connect to
+///
+/// Sockets are created with permissions from the umask, so it's bound in a
+/// private directory and only moved into place once restricted.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let temp = dir.join(CONSOLE_SOCKET);
+    let result = UnixListener::bind(&temp).and_then(|listener| {
+        std::fs::set_permissions(&temp, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&temp, path)?;
+
+        Ok(listener)
+    });
+
+    let _ = std::fs::remove_file(&temp);
+    let _ = std::fs::remove_dir(&dir);
+
+    result
+}
+
+#[cfg(unix)]
+async fn serve_client(console: Arc<Console>, stream: UnixStream, input: mpsc::Sender<String>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = LossyLines::new(reader);
+
+    let mode = match lines.next_line().await {
+        Ok(Some(line)) => Mode::parse(&line).unwrap_or(Mode::Read),
+        _ => return,
+    };
+    let is_writer = mode == Mode::Write && console.claim_writer();
+
+    let (scrollback, mut output) = console.subscribe();
+    let mut greeting = scrollback;
+
+    match (mode, is_writer) {
+        (Mode::Write, false) => greeting.push(format!(
+            "{} Another client is sending commands, attached read-only",
+            MESSAGE_PREFIX
+        )),
+        (Mode::Write, true) => greeting.push(format!(
+            "{} Attached, commands are sent to the server",
+            MESSAGE_PREFIX
+        )),
+        (Mode::Read, _) => greeting.push(format!("{} Attached read-only", MESSAGE_PREFIX)),
+    }
+
+    let result = async {
+        for line in greeting {
+            writer.write_all(format!("{}\n", line).as_bytes()).await?;
+        }
+
+        loop {
+            tokio::select! {
+                line = output.recv() => {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            format!("{} {} lines skipped", MESSAGE_PREFIX, n)
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+
+                    writer.write_all(format!("{}\n", line).as_bytes()).await?;
+                }
+                line = lines.next_line() => match line? {
+                    Some(line) if is_writer => {
+                        if input.send(line).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(_) => {
+                        writer
+                            .write_all(
+                                format!("{} Read-only, command ignored\n", MESSAGE_PREFIX).as_bytes(),
+                            )
+                            .await?;
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        Ok::<(), io::Error>(())
+    };
+
+    if let Err(e) = result.await {
+        debug!("Console client disconnected: {}", e);
+    }
+
+    if is_writer {
+        console.release_writer();
+    }
+}
+
+/// Attaches the terminal to a running server's console until stdin closes
+#[cfg(unix)]
+pub async fn attach(path: &Path, mode: Mode) -> Result<(), ConsoleError> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(ConsoleError::Socket)?;
+    let (reader, mut writer) = stream.into_split();
+
+    writer
+        .write_all(format!("{}\n", mode.as_str()).as_bytes())
+        .await
+        .map_err(ConsoleError::Socket)?;
+
+    let mut output = LossyLines::new(reader);
+    let mut input = LossyLines::new(tokio::io::stdin());
+    let mut input_open = true;
+
+    loop {
+        tokio::select! {
+            line = output.next_line() => match line.map_err(ConsoleError::Socket)? {
+                Some(line) => println!("{}", line),
+                None => {
+                    println!("{} Server console closed", MESSAGE_PREFIX);
+                    break;
+                }
+            },
+            line = input.next_line(), if input_open => match line.map_err(ConsoleError::Socket)? {
+                Some(line) => {
+                    writer
+                        .write_all(format!("{}\n", line).as_bytes())
+                        .await
+                        .map_err(ConsoleError::Socket)?;
+                }
+                // Detach on Ctrl+D, unless only reading
+                None if mode == Mode::Write => break,
+                None => input_open = false,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Client connected to `serve_client` over a socket pair
+    struct Client {
+        lines: LossyLines<tokio::net::unix::OwnedReadHalf>,
+        writer: tokio::net::unix::OwnedWriteHalf,
+    }
+
+    impl Client {
+        async fn connect(console: &Arc<Console>, input: &mpsc::Sender<String>, mode: &str) -> Self {
+            let (client, server) = UnixStream::pair().unwrap();
+            tokio::spawn(serve_client(console.clone(), server, input.clone()));
+
+            let (reader, writer) = client.into_split();
+            let mut client = Self {
+                lines: LossyLines::new(reader),
+                writer,
+            };
+
+            client.send(mode.as_bytes()).await;
+            client
+        }
+        async fn send(&mut self, line: &[u8]) {
+            self.writer.write_all(line).await.unwrap();
+            self.writer.write_all(b"\n").await.unwrap();
+        }
+        async fn line(&mut self) -> String {
+            tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrollback() {
+        let console = Console::new();
+        let (input, _rx) = mpsc::channel(16);
+
+        console.push("first".to_string());
+        console.push("second".to_string());
+
+        let mut client = Client::connect(&console, &input, "read").await;
+
+        assert_eq!(client.line().await, "first");
+        assert_eq!(client.line().await, "second");
+        assert_eq!(client.line().await, "[vkconstruct] Attached read-only");
+
+        console.push("third".to_string());
+        assert_eq!(client.line().await, "third");
+    }
+
+    #[tokio::test]
+    async fn test_writer_and_observers() {
+        let console = Console::new();
+        let (input, mut rx) = mpsc::channel(16);
+
+        let mut writer = Client::connect(&console, &input, "write").await;
+        assert_eq!(
+            writer.line().await,
+            "[vkconstruct] Attached, commands are sent to the server"
+        );
+
+        let mut observers = [
+            Client::connect(&console, &input, "write").await,
+            Client::connect(&console, &input, "read").await,
+        ];
+        assert_eq!(
+            observers[0].line().await,
+            "[vkconstruct] Another client is sending commands, attached read-only"
+        );
+        assert_eq!(
+            observers[1].line().await,
+            "[vkconstruct] Attached read-only"
+        );
+
+        // Invalid UTF-8 doesn't end the connection
+        writer.send(b"say \xff").await;
+        assert_eq!(rx.recv().await.unwrap(), "say \u{fffd}");
+
+        for observer in observers.iter_mut() {
+            observer.send(b"op me").await;
+            assert_eq!(
+                observer.line().await,
+                "[vkconstruct] Read-only, command ignored"
+            );
+        }
+
+        writer.send(b"list").await;
+        assert_eq!(rx.recv().await.unwrap(), "list");
+
+        console.push("output".to_string());
+
+        for client in observers.iter_mut().chain([&mut writer]) {
+            assert_eq!(client.line().await, "output");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(CONSOLE_SOCKET);
+        let console = Console::new();
+        let (input, _rx) = mpsc::channel(16);
+
+        let listener = listen(&console, &path, input.clone()).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Only the socket is left behind
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 1);
+
+        assert!(matches!(
+            listen(&console, &path, input).await,
+            Err(ConsoleError::InUse(_))
+        ));
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -2,12 +2,37 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
 use crate::hostinfo;
+use crate::template::resource::ResourceLimits;
 
+pub mod console;
 pub mod script;
 
 mod run;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod shutdown;
+mod supervise;
 
 pub use run::run;
+pub use shutdown::ShutdownOptions;
+pub use supervise::{RestartOptions, RestartPolicy};
+
+/// Options for `run`
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Runs the server sandboxed if set
+    pub sandbox: Option<SandboxOptions>,
+    pub restart: RestartOptions,
+    pub shutdown: ShutdownOptions,
+}
+
+/// Options for running the server in a sandbox (Linux only)
+#[derive(Debug, Clone, Default)]
+pub struct SandboxOptions {
+    /// Delegated cgroup to create the server's cgroup in. Defaults to the
+    /// cgroup of the current process
+    pub cgroup_parent: Option<PathBuf>,
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BuildExecInfo {
@@ -21,4 +46,76 @@ pub struct BuildExecInfo {
     /// Arguments for the runtime executable
     #[serde(rename = "runtime-args")]
     pub args: Vec<String>,
+    /// Limits applied when running sandboxed
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Console command for a graceful shutdown
+    #[serde(
+        rename = "stop-command",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop_command: Option<String>,
+}
+
+/// Minimum memory allowed on top of the JVM heap for metaspace, threads and
+/// native buffers
+const MIN_NON_HEAP_MB: u64 = 256;
+
+/// Parses a JVM memory size (e.g. `4G`, `512m`, `1048576`) into mebibytes
+fn parse_jvm_size(size: &str) -> Option<u64> {
+    let (digits, multiplier) = match size.chars().last()? {
+        'k' | 'K' => (&size[..size.len() - 1], 1024),
+        'm' | 'M' => (&size[..size.len() - 1], 1024 * 1024),
+        'g' | 'G' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        't' | 'T' => (&size[..size.len() - 1], 1024 * 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(multiplier)
+        .map(|b| b / 1024 / 1024)
+}
+
+/// Fills in limits missing from the template, deriving the memory limit from
+/// the maximum heap size passed to the JVM
+pub fn derive_limits(limits: Option<&ResourceLimits>, args: &[String]) -> ResourceLimits {
+    let mut limits = limits.cloned().unwrap_or_default();
+
+    if limits.memory_mb.is_none() {
+        limits.memory_mb = args
+            .iter()
+            .filter_map(|a| a.strip_prefix("-Xmx"))
+            .filter_map(parse_jvm_size)
+            .next_back()
+            .map(|heap| heap + (heap / 4).max(MIN_NON_HEAP_MB));
+    }
+
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_limits() {
+        let args = vec!["-Xms512M".to_string(), "-Xmx4G".to_string()];
+
+        assert_eq!(derive_limits(None, &args).memory_mb, Some(5120));
+        assert_eq!(
+            derive_limits(None, &["-Xmx512m".to_string()]).memory_mb,
+            Some(768)
+        );
+        assert_eq!(derive_limits(None, &[]).memory_mb, None);
+
+        let limits = ResourceLimits {
+            memory_mb: Some(2048),
+            cpus: Some(2.0),
+        };
+
+        assert_eq!(derive_limits(Some(&limits), &args), limits);
+    }
 }
@@ -1,12 +1,22 @@
-use std::{path, process};
-use tracing::{debug, error, info};
+use std::{path, process, time::Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
 
 use crate::{
     build::{BuildInfo, BuildInfoError},
+    duration::format_duration,
     hostinfo,
     vkstore::VolkanicStore,
 };
 
+use super::console::{self, Console, LossyLines};
+use super::shutdown::{self, Shutdown, ShutdownSignals};
+use super::supervise::{Decision, Exit, Supervisor};
+use super::RunOptions;
+
+#[cfg(target_os = "linux")]
+use super::sandbox::{Sandbox, SandboxError};
+
 #[cfg(target_os = "windows")]
 use crate::resources;
 
@@ -30,6 +40,24 @@ pub enum ExecutionError {
     ChildProcessFailed,
     #[error("Child process closed with error code: {0}")]
     ChildProcessFailedCode(i32),
+    #[error("Server restarted {0} times within {1}, giving up")]
+    CrashLoop(usize, String),
+    #[error("Console error: {0}")]
+    Console(console::ConsoleError),
+    #[error("Failed to listen for signals: {0}")]
+    Signal(std::io::Error),
+    #[error("Server didn't stop in time and was terminated")]
+    Terminated,
+    #[error("Server didn't stop in time and was killed")]
+    Killed,
+    #[cfg(target_os = "linux")]
+    #[error("Sandbox error: {0}")]
+    Sandbox(SandboxError),
+    #[error("Sandbox setup failed (are unprivileged user namespaces enabled?): {0}")]
+    SandboxSetupFailed(std::io::Error),
+    #[cfg(not(target_os = "linux"))]
+    #[error("Sandboxing is only supported on Linux")]
+    SandboxUnsupported,
 }
 
 // Prevent "dead_code" warning when compiling on non-Windows targets
@@ -41,7 +69,42 @@ pub async fn winpath_fix(path: impl std::fmt::Display) -> String {
     path.to_string().replace("\\\\?\\", "")
 }
 
-pub async fn run(store: &VolkanicStore) -> Result<(), ExecutionError> {
+impl ExecutionError {
+    /// Process exit code describing how the server ended
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ExecutionError::ChildProcessFailedCode(code) => *code,
+            // Shell convention: 128 + signal number
+            ExecutionError::Terminated => 128 + 15,
+            ExecutionError::Killed => 128 + 9,
+            _ => 1,
+        }
+    }
+}
+
+/// How a server process ended
+enum Ended {
+    Exited(process::ExitStatus),
+    Stopped(Shutdown),
+}
+
+/// Forwards lines from our stdin so the server console stays usable
+fn forward_stdin(tx: mpsc::Sender<String>) {
+    tokio::spawn(async move {
+        let mut lines = LossyLines::new(tokio::io::stdin());
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+pub async fn run(store: &VolkanicStore, options: &RunOptions) -> Result<(), ExecutionError> {
+    let sandbox = options.sandbox.as_ref();
+    let restart = &options.restart;
+
     // Check if build information exists
     if !BuildInfo::exists(store).await {
         error!("There's no build in the current directory!");
@@ -142,32 +205,175 @@ pub async fn run(store: &VolkanicStore) -> Result<(), ExecutionError> {
         command.1.join(" "),
     );
 
-    let mut server_proc = process::Command::new(command.0)
+    let mut server_command = process::Command::new(command.0);
+
+    server_command
         .args(command.1)
         .current_dir(&store.build_path)
-        .spawn()
-        .map_err(ExecutionError::ChildProcessSpawnFailed)?;
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped());
+
+    // Keep terminal signals away from the server so it's stopped through its
+    // console instead
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut server_command, 0);
+
+    #[cfg(target_os = "linux")]
+    let _sandbox = match sandbox {
+        Some(options) => Some(
+            Sandbox::apply(&mut server_command, store, &exec_info.limits, options)
+                .map_err(ExecutionError::Sandbox)?,
+        ),
+        None => None,
+    };
+    #[cfg(not(target_os = "linux"))]
+    if sandbox.is_some() {
+        return Err(ExecutionError::SandboxUnsupported);
+    }
+
+    let mut server_command = tokio::process::Command::from(server_command);
+
+    server_command.kill_on_drop(true);
+
+    let stop_command = exec_info
+        .stop_command
+        .as_deref()
+        .unwrap_or(shutdown::DEFAULT_STOP_COMMAND);
+    let mut signals = ShutdownSignals::new().map_err(ExecutionError::Signal)?;
+    let (input_tx, mut input) = mpsc::channel(16);
+    let console = Console::new();
 
-    info!("Spawned server process");
+    #[cfg(unix)]
+    let _console_listener =
+        console::listen(&console, &console::socket_path(store), input_tx.clone())
+            .await
+            .map_err(ExecutionError::Console)?;
 
-    let exit_status = server_proc
-        .wait()
-        .map_err(ExecutionError::ChildProcessSpawnFailed)?;
+    forward_stdin(input_tx);
 
-    if !exit_status.success() {
-        match exit_status.code() {
-            Some(code) => {
-                error!("Child process failed with error code: {}", code);
-                return Err(ExecutionError::ChildProcessFailedCode(code));
+    let mut input_open = true;
+    let mut supervisor = Supervisor::new(restart.clone());
+
+    loop {
+        let mut server_proc = server_command.spawn().map_err(|e| {
+            if sandbox.is_some() {
+                ExecutionError::SandboxSetupFailed(e)
+            } else {
+                ExecutionError::ChildProcessSpawnFailed(e)
             }
-            None => {
-                error!("Child process failed");
-                return Err(ExecutionError::ChildProcessFailed);
+        })?;
+        let mut server_stdin = server_proc.stdin.take();
+
+        let mut output_tasks = vec![];
+
+        if let Some(stdout) = server_proc.stdout.take() {
+            output_tasks.push(console.pipe(stdout, false));
+        }
+        if let Some(stderr) = server_proc.stderr.take() {
+            output_tasks.push(console.pipe(stderr, true));
+        }
+
+        match sandbox {
+            Some(_) => info!("Spawned sandboxed server process"),
+            None => info!("Spawned server process"),
+        }
+
+        let ended = loop {
+            tokio::select! {
+                status = server_proc.wait() => {
+                    break Ended::Exited(status.map_err(ExecutionError::ChildProcessSpawnFailed)?);
+                }
+                line = input.recv(), if input_open => match (line, server_stdin.as_mut()) {
+                    (Some(line), Some(stdin)) => {
+                        if let Err(e) = shutdown::write_line(stdin, &line).await {
+                            debug!("Unable to write to server console: {}", e);
+                        }
+                    }
+                    (Some(_), None) => {}
+                    (None, _) => input_open = false,
+                },
+                _ = signals.recv() => {
+                    let stopped = shutdown::stop_server(
+                        &mut server_proc,
+                        server_stdin.as_mut(),
+                        stop_command,
+                        &options.shutdown,
+                        &mut signals,
+                    )
+                    .await
+                    .map_err(ExecutionError::ChildProcessSpawnFailed)?;
+
+                    break Ended::Stopped(stopped);
+                }
+            }
+        };
+
+        // Let the last lines of output reach the terminal and clients
+        for task in output_tasks {
+            let _ = task.await;
+        }
+
+        let exit_status = match ended {
+            Ended::Exited(status) => status,
+            Ended::Stopped(stopped) => {
+                return match stopped {
+                    Shutdown::Graceful(status) if status.success() => {
+                        info!("Server stopped cleanly");
+                        Ok(())
+                    }
+                    Shutdown::Graceful(status) => match status.code() {
+                        Some(code) => Err(ExecutionError::ChildProcessFailedCode(code)),
+                        None => Err(ExecutionError::ChildProcessFailed),
+                    },
+                    Shutdown::Terminated => Err(ExecutionError::Terminated),
+                    Shutdown::Killed => Err(ExecutionError::Killed),
+                };
+            }
+        };
+
+        let exit = if exit_status.success() {
+            info!("Server stopped cleanly");
+            Exit::Clean
+        } else {
+            match exit_status.code() {
+                Some(code) => error!("Child process failed with error code: {}", code),
+                None => error!("Child process failed"),
+            }
+            Exit::Crashed(exit_status.code())
+        };
+
+        match supervisor.next(exit, Instant::now()) {
+            Decision::Stop => break exit_result(exit),
+            Decision::Restart(delay) => {
+                warn!("Restarting server in {}", format_duration(delay));
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = signals.recv() => {
+                        info!("Not restarting, shutdown requested");
+                        break exit_result(exit);
+                    }
+                }
+            }
+            Decision::CrashLoop => {
+                error!("Server is crash looping, not restarting");
+                break Err(ExecutionError::CrashLoop(
+                    restart.max_restarts,
+                    format_duration(restart.window),
+                ));
             }
         }
     }
+}
 
-    Ok(())
+/// Result of a run ending after the given exit
+fn exit_result(exit: Exit) -> Result<(), ExecutionError> {
+    match exit {
+        Exit::Clean => Ok(()),
+        Exit::Crashed(Some(code)) => Err(ExecutionError::ChildProcessFailedCode(code)),
+        Exit::Crashed(None) => Err(ExecutionError::ChildProcessFailed),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,856 @@
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::{fs, io, process};
+use tracing::{debug, warn};
+
+use crate::template::resource::ResourceLimits;
+use crate::vkstore::VolkanicStore;
+
+use super::SandboxOptions;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Period used for `cpu.max`, in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Host directories exposed read-only so dynamically linked runtimes work
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/run/systemd/resolve",
+];
+/// Device nodes exposed to the server
+const DEVICES: &[&str] = &[
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+];
+const DEVICE_LINKS: &[(&str, &str)] = &[
+    ("/proc/self/fd", "/dev/fd"),
+    ("/proc/self/fd/0", "/dev/stdin"),
+    ("/proc/self/fd/1", "/dev/stdout"),
+    ("/proc/self/fd/2", "/dev/stderr"),
+];
+/// Writable, empty directories
+const TMPFS_DIRS: &[&str] = &["/tmp", "/dev/shm"];
+/// Signals passed on to the server by the process waiting for it
+const RELAYED_SIGNALS: &[libc::c_int] = &[
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// System calls refused with `EPERM` inside the sandbox
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_acct,
+    libc::SYS_add_key,
+    libc::SYS_bpf,
+    libc::SYS_chroot,
+    libc::SYS_delete_module,
+    libc::SYS_finit_module,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fsopen,
+    libc::SYS_init_module,
+    libc::SYS_kexec_file_load,
+    libc::SYS_kexec_load,
+    libc::SYS_keyctl,
+    libc::SYS_mount,
+    libc::SYS_mount_setattr,
+    libc::SYS_move_mount,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_open_tree,
+    libc::SYS_perf_event_open,
+    libc::SYS_pivot_root,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_ptrace,
+    libc::SYS_quotactl,
+    libc::SYS_reboot,
+    libc::SYS_request_key,
+    libc::SYS_setns,
+    libc::SYS_swapoff,
+    libc::SYS_swapon,
+    libc::SYS_syslog,
+    libc::SYS_umount2,
+    libc::SYS_unshare,
+    libc::SYS_userfaultfd,
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum SandboxError {
+    #[error("Filesystem error: {0}")]
+    Filesystem(io::Error),
+    #[error("Path contains a NUL byte: {0}")]
+    InvalidPath(PathBuf),
+    #[error("Cgroup error: {0}")]
+    Cgroup(io::Error),
+    #[error("Resource limits require the cgroup v2 unified hierarchy")]
+    CgroupV2Unavailable,
+    #[error("Cgroup controller \"{0}\" is unavailable (try a delegated --cgroup-parent)")]
+    ControllerUnavailable(&'static str),
+    #[error("Seccomp filter error: {0}")]
+    Seccomp(seccompiler::BackendError),
+    #[error("Seccomp is not supported on this architecture")]
+    UnsupportedArchitecture,
+    #[error("Memory limit is too large: {0} MiB")]
+    MemoryLimitTooLarge(u64),
+}
+
+fn c_path<P: AsRef<Path>>(path: P) -> Result<CString, SandboxError> {
+    CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|_| SandboxError::InvalidPath(path.as_ref().to_path_buf()))
+}
+
+/// Returns `-1` results as the last OS error
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes to a file using only async-signal-safe calls
+fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    check(fd)?;
+
+    let written = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+    let write_err = io::Error::last_os_error();
+
+    unsafe { libc::close(fd) };
+
+    if written != data.len() as isize {
+        return Err(write_err);
+    }
+
+    Ok(())
+}
+
+/// Creates a directory, ignoring directories which already exist
+fn make_dir(path: &CStr) -> io::Result<()> {
+    match check(unsafe { libc::mkdir(path.as_ptr(), 0o755) }) {
+        Err(e) if e.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+        r => r,
+    }
+}
+
+/// Flags which must be kept when remounting a bind mount inside a user
+/// namespace
+fn locked_flags(path: &CStr) -> io::Result<libc::c_ulong> {
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::statvfs(path.as_ptr(), &mut stat) })?;
+
+    let mut flags = 0;
+
+    for (st, ms) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+    ] {
+        if stat.f_flag & st != 0 {
+            flags |= ms;
+        }
+    }
+
+    Ok(flags)
+}
+
+#[derive(Debug)]
+enum MountKind {
+    Bind {
+        source: CString,
+        file: bool,
+        /// Flags for the read-only remount, if the bind isn't writable
+        read_only: Option<libc::c_ulong>,
+    },
+    Tmpfs,
+    Symlink {
+        source: CString,
+    },
+    /// Procfs of the sandbox's own PID namespace
+    Proc,
+    /// Cgroup2 filesystem of the sandbox's own cgroup namespace, skipped if
+    /// the host doesn't allow it
+    Cgroup,
+}
+
+#[derive(Debug)]
+struct Mount {
+    kind: MountKind,
+    /// Directories to create inside the new root before mounting
+    parents: Vec<CString>,
+    target: CString,
+    depth: usize,
+}
+
+/// New root filesystem layout, prepared before forking because only
+/// async-signal-safe calls are allowed between `fork` and `exec`
+#[derive(Debug)]
+struct MountPlan {
+    root: CString,
+    mounts: Vec<Mount>,
+    cwd: CString,
+}
+
+impl MountPlan {
+    fn new(root: &Path, cwd: &Path) -> Result<Self, SandboxError> {
+        Ok(Self {
+            root: c_path(root)?,
+            mounts: vec![],
+            cwd: c_path(cwd)?,
+        })
+    }
+    fn add(&mut self, root: &Path, target: &Path, kind: MountKind) -> Result<(), SandboxError> {
+        let rel_target = target.strip_prefix("/").unwrap_or(target);
+        let mut parents = vec![];
+        let mut current = root.to_path_buf();
+
+        if let Some(parent) = rel_target.parent() {
+            for c in parent.components() {
+                current.push(c);
+                parents.push(c_path(&current)?);
+            }
+        }
+
+        self.mounts.push(Mount {
+            kind,
+            parents,
+            target: c_path(root.join(rel_target))?,
+            depth: rel_target.components().count(),
+        });
+
+        // Outer mounts have to exist before nested ones
+        self.mounts.sort_by_key(|m| m.depth);
+
+        Ok(())
+    }
+    fn bind(&mut self, root: &Path, path: &Path, writable: bool) -> Result<(), SandboxError> {
+        let source = c_path(path)?;
+        let read_only = if writable {
+            None
+        } else {
+            Some(locked_flags(&source).map_err(SandboxError::Filesystem)?)
+        };
+
+        self.add(
+            root,
+            path,
+            MountKind::Bind {
+                source,
+                file: !path.is_dir(),
+                read_only,
+            },
+        )
+    }
+    /// Runs in the forked child: builds the new root and switches to it
+    fn enter(&self) -> io::Result<()> {
+        let none = std::ptr::null();
+
+        unsafe {
+            check(libc::mount(
+                none,
+                c"/".as_ptr(),
+                none,
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                c"tmpfs".as_ptr(),
+                self.root.as_ptr(),
+                c"tmpfs".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                c"mode=0755".as_ptr().cast(),
+            ))?;
+        }
+
+        for m in &self.mounts {
+            for p in &m.parents {
+                make_dir(p)?;
+            }
+
+            match &m.kind {
+                MountKind::Bind {
+                    source,
+                    file,
+                    read_only,
+                } => {
+                    if *file {
+                        let fd = unsafe {
+                            libc::open(
+                                m.target.as_ptr(),
+                                libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                                0o644,
+                            )
+                        };
+                        check(fd)?;
+                        unsafe { libc::close(fd) };
+                    } else {
+                        make_dir(&m.target)?;
+                    }
+
+                    check(unsafe {
+                        libc::mount(
+                            source.as_ptr(),
+                            m.target.as_ptr(),
+                            none,
+                            libc::MS_BIND | libc::MS_REC,
+                            std::ptr::null(),
+                        )
+                    })?;
+
+                    if let Some(flags) = read_only {
+                        check(unsafe {
+                            libc::mount(
+                                none,
+                                m.target.as_ptr(),
+                                none,
+                                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | flags,
+                                std::ptr::null(),
+                            )
+                        })?;
+                    }
+                }
+                MountKind::Tmpfs => {
+                    make_dir(&m.target)?;
+
+                    check(unsafe {
+                        libc::mount(
+                            c"tmpfs".as_ptr(),
+                            m.target.as_ptr(),
+                            c"tmpfs".as_ptr(),
+                            libc::MS_NOSUID | libc::MS_NODEV,
+                            c"mode=1777".as_ptr().cast(),
+                        )
+                    })?;
+                }
+                MountKind::Symlink { source } => {
+                    check(unsafe { libc::symlink(source.as_ptr(), m.target.as_ptr()) })?;
+                }
+                MountKind::Proc => {
+                    make_dir(&m.target)?;
+
+                    check(unsafe {
+                        libc::mount(
+                            c"proc".as_ptr(),
+                            m.target.as_ptr(),
+                            c"proc".as_ptr(),
+                            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                            std::ptr::null(),
+                        )
+                    })?;
+                }
+                MountKind::Cgroup => {
+                    make_dir(&m.target)?;
+
+                    // Runtimes only use it to detect their limits
+                    unsafe {
+                        libc::mount(
+                            c"cgroup2".as_ptr(),
+                            m.target.as_ptr(),
+                            c"cgroup2".as_ptr(),
+                            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC | libc::MS_RDONLY,
+                            std::ptr::null(),
+                        )
+                    };
+                }
+            }
+        }
+
+        unsafe {
+            check(libc::chdir(self.root.as_ptr()))?;
+            // Stacks the old root on top of the new one, then detaches it
+            check(
+                libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as libc::c_int,
+            )?;
+            check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            check(libc::mount(
+                none,
+                c"/".as_ptr(),
+                none,
+                libc::MS_BIND
+                    | libc::MS_REMOUNT
+                    | libc::MS_RDONLY
+                    | libc::MS_NOSUID
+                    | libc::MS_NODEV,
+                std::ptr::null(),
+            ))?;
+            check(libc::chdir(self.cwd.as_ptr()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Child cgroup holding the server process, removed when dropped
+#[derive(Debug)]
+struct Cgroup {
+    path: PathBuf,
+    procs: OwnedFd,
+    /// Leaf cgroup this process moved into to enable controllers in its own
+    /// cgroup, left again when dropped
+    leaf: Option<PathBuf>,
+}
+
+/// Formats a number of cores for `cpu.max`
+fn cpu_max(cpus: f64) -> String {
+    let quota = ((cpus * CPU_PERIOD_US as f64) as u64).max(1000);
+
+    format!("{} {}", quota, CPU_PERIOD_US)
+}
+
+/// Finds the cgroup v2 directory of the current process
+fn current_cgroup() -> Result<PathBuf, SandboxError> {
+    // Legacy and hybrid hierarchies don't mount cgroup v2 at the root
+    if !Path::new(CGROUP_ROOT).join("cgroup.controllers").is_file() {
+        return Err(SandboxError::CgroupV2Unavailable);
+    }
+
+    let contents = fs::read_to_string("/proc/self/cgroup").map_err(SandboxError::Cgroup)?;
+
+    contents
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .map(|p| Path::new(CGROUP_ROOT).join(p.trim_start_matches('/')))
+        .ok_or(SandboxError::CgroupV2Unavailable)
+}
+
+impl Cgroup {
+    fn create(parent: Option<&Path>, limits: &ResourceLimits) -> Result<Self, SandboxError> {
+        let parent = match parent {
+            Some(p) => p.to_path_buf(),
+            None => current_cgroup()?,
+        };
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let mut leaf = None;
+
+        if let Err(e) = fs::write(&subtree_control, "+memory +cpu") {
+            // Cgroups with processes can't hand out controllers, so move
+            // ourselves into a leaf first
+            if e.raw_os_error() == Some(libc::EBUSY) {
+                let path = parent.join(format!("vkconstruct-{}", process::id()));
+
+                fs::create_dir_all(&path).map_err(SandboxError::Cgroup)?;
+                fs::write(path.join("cgroup.procs"), "0").map_err(SandboxError::Cgroup)?;
+
+                leaf = Some(path);
+
+                if let Err(e) = fs::write(&subtree_control, "+memory +cpu") {
+                    debug!("Unable to enable cgroup controllers: {}", e);
+                }
+            } else {
+                debug!("Unable to enable cgroup controllers: {}", e);
+            }
+        }
+
+        let path = parent.join(format!("vkconstruct-{}", uuid::Uuid::new_v4()));
+
+        fs::create_dir(&path).map_err(SandboxError::Cgroup)?;
+
+        let cgroup = Self {
+            procs: fs::OpenOptions::new()
+                .write(true)
+                .open(path.join("cgroup.procs"))
+                .map_err(SandboxError::Cgroup)?
+                .into(),
+            path,
+            leaf,
+        };
+
+        if let Some(memory) = limits.memory_mb {
+            let bytes = memory
+                .checked_mul(1024 * 1024)
+                .ok_or(SandboxError::MemoryLimitTooLarge(memory))?;
+
+            cgroup.set("memory", "memory.max", &bytes.to_string())?;
+            cgroup.set("memory", "memory.swap.max", "0").ok();
+        }
+
+        if let Some(cpus) = limits.cpus {
+            cgroup.set("cpu", "cpu.max", &cpu_max(cpus))?;
+        }
+
+        debug!("Created cgroup: \"{}\"", cgroup.path.to_string_lossy());
+
+        Ok(cgroup)
+    }
+    fn set(&self, controller: &'static str, file: &str, value: &str) -> Result<(), SandboxError> {
+        let path = self.path.join(file);
+
+        if !path.is_file() {
+            return Err(SandboxError::ControllerUnavailable(controller));
+        }
+
+        fs::write(path, value).map_err(SandboxError::Cgroup)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            warn!(
+                "Failed to remove cgroup \"{}\": {}",
+                self.path.to_string_lossy(),
+                e
+            );
+        }
+
+        if let Some(leaf) = &self.leaf {
+            if let Err(e) = leave_leaf(leaf) {
+                warn!(
+                    "Failed to remove cgroup \"{}\": {}",
+                    leaf.to_string_lossy(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Moves this process from its leaf cgroup back to the parent and removes
+/// the leaf
+///
+/// The parent can only hold processes once its controllers are disabled,
+/// so the leaf is kept while other cgroups may use them.
+fn leave_leaf(leaf: &Path) -> io::Result<()> {
+    let parent = leaf.parent().unwrap_or(leaf);
+
+    for e in fs::read_dir(parent)? {
+        let e = e?;
+
+        if e.path() != leaf && e.file_type()?.is_dir() {
+            debug!(
+                "Keeping cgroup \"{}\" next to \"{}\"",
+                leaf.to_string_lossy(),
+                e.path().to_string_lossy()
+            );
+            return Ok(());
+        }
+    }
+
+    fs::write(parent.join("cgroup.subtree_control"), "-memory -cpu")?;
+    fs::write(parent.join("cgroup.procs"), "0")?;
+    fs::remove_dir(leaf)
+}
+
+fn seccomp_filter() -> Result<BpfProgram, SandboxError> {
+    let arch = TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|_| SandboxError::UnsupportedArchitecture)?;
+    // `c_long` is narrower than `i64` on 32-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    let rules = DENIED_SYSCALLS
+        .iter()
+        .map(|s| (*s as i64, vec![]))
+        .collect::<BTreeMap<_, _>>();
+
+    SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::Errno(libc::EPERM as u32),
+        arch,
+    )
+    .and_then(BpfProgram::try_from)
+    .map_err(SandboxError::Seccomp)
+}
+
+/// PID of the server in the process relaying signals to it
+static SERVER_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn relay_signal(signal: libc::c_int) {
+    let pid = SERVER_PID.load(Ordering::Relaxed);
+
+    if pid > 0 {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
+
+/// Runs in the process which created the PID namespace, waiting for the
+/// server (PID 1 inside it) and then exiting the same way
+///
+/// Only async-signal-safe calls are allowed, as in `MountPlan::enter`.
+fn relay(pid: libc::pid_t) -> ! {
+    SERVER_PID.store(pid, Ordering::Relaxed);
+
+    unsafe {
+        // Spawning only succeeds once every copy of its error pipe is closed
+        if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) != 0 {
+            for fd in 3..1024 {
+                libc::close(fd);
+            }
+        }
+
+        for signal in RELAYED_SIGNALS {
+            libc::signal(
+                *signal,
+                relay_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+
+        let mut status = 0;
+
+        while libc::waitpid(pid, &mut status, 0) == -1 {
+            if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                libc::_exit(1);
+            }
+        }
+
+        if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            let no_core = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+
+            libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+            libc::signal(signal, libc::SIG_DFL);
+            libc::kill(libc::getpid(), signal);
+        }
+
+        libc::_exit(if libc::WIFEXITED(status) {
+            libc::WEXITSTATUS(status)
+        } else {
+            1
+        })
+    }
+}
+
+/// Keeps sandbox resources alive while the server runs
+#[derive(Debug)]
+pub struct Sandbox {
+    _cgroup: Option<Cgroup>,
+}
+
+impl Sandbox {
+    /// Configures the command to start in new user, mount and PID namespaces
+    /// which only expose the build and runtime directories, limited by a
+    /// cgroup and a seccomp filter
+    pub fn apply(
+        command: &mut process::Command,
+        store: &VolkanicStore,
+        limits: &ResourceLimits,
+        options: &SandboxOptions,
+    ) -> Result<Self, SandboxError> {
+        let build_path = store
+            .build_path
+            .canonicalize()
+            .map_err(SandboxError::Filesystem)?;
+        let runtime_path = store
+            .runtime_path
+            .canonicalize()
+            .map_err(SandboxError::Filesystem)?;
+        let root = store.temp_path.join("sandbox");
+
+        fs::create_dir_all(&root).map_err(SandboxError::Filesystem)?;
+
+        let root = root.canonicalize().map_err(SandboxError::Filesystem)?;
+
+        let mut plan = MountPlan::new(&root, &build_path)?;
+
+        for dir in SYSTEM_DIRS.iter().map(Path::new).filter(|p| p.exists()) {
+            // Keep merged-/usr links (e.g. "/lib" -> "usr/lib") as links
+            match fs::read_link(dir) {
+                Ok(source) => plan.add(
+                    &root,
+                    dir,
+                    MountKind::Symlink {
+                        source: c_path(source)?,
+                    },
+                )?,
+                Err(_) => plan.bind(&root, dir, false)?,
+            }
+        }
+
+        for dev in DEVICES.iter().map(Path::new).filter(|p| p.exists()) {
+            plan.bind(&root, dev, true)?;
+        }
+
+        for (source, target) in DEVICE_LINKS {
+            plan.add(
+                &root,
+                Path::new(target),
+                MountKind::Symlink {
+                    source: c_path(source)?,
+                },
+            )?;
+        }
+
+        for dir in TMPFS_DIRS {
+            plan.add(&root, Path::new(dir), MountKind::Tmpfs)?;
+        }
+
+        // The host's would show its processes and hardware
+        plan.add(&root, Path::new("/proc"), MountKind::Proc)?;
+        plan.add(&root, Path::new("/sys/fs/cgroup"), MountKind::Cgroup)?;
+
+        plan.bind(&root, &runtime_path, false)?;
+        plan.bind(&root, &build_path, true)?;
+
+        let cgroup = if limits.memory_mb.is_some() || limits.cpus.is_some() {
+            Some(Cgroup::create(options.cgroup_parent.as_deref(), limits)?)
+        } else {
+            warn!("No resource limits set for sandbox");
+            None
+        };
+        let cgroup_fd = cgroup.as_ref().map(|c| c.procs.as_raw_fd());
+
+        let setgroups = c"/proc/self/setgroups";
+        let uid_map_path = c"/proc/self/uid_map";
+        let gid_map_path = c"/proc/self/gid_map";
+        // Keep the same IDs inside the namespace so files stay owned by the user
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let uid_map = format!("{} {} 1", uid, uid);
+        let gid_map = format!("{} {} 1", gid, gid);
+
+        let filter = seccomp_filter()?;
+
+        debug!("Sandbox root: \"{}\"", root.to_string_lossy());
+
+        command.env("HOME", &build_path);
+
+        unsafe {
+            command.pre_exec(move || {
+                check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+
+                if let Some(fd) = cgroup_fd {
+                    if libc::write(fd, b"0".as_ptr().cast(), 1) != 1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+
+                check(libc::unshare(
+                    libc::CLONE_NEWUSER
+                        | libc::CLONE_NEWNS
+                        | libc::CLONE_NEWPID
+                        | libc::CLONE_NEWIPC
+                        | libc::CLONE_NEWCGROUP,
+                ))?;
+
+                write_file(setgroups, b"deny")?;
+                write_file(uid_map_path, uid_map.as_bytes())?;
+                write_file(gid_map_path, gid_map.as_bytes())?;
+
+                // Only children join the new PID namespace, so the server
+                // continues in one while this process waits for it
+                let pid = libc::fork();
+                check(pid)?;
+
+                if pid > 0 {
+                    relay(pid);
+                }
+
+                check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+
+                plan.enter()?;
+
+                seccompiler::apply_filter(&filter)
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EPERM))
+            });
+        }
+
+        Ok(Self { _cgroup: cgroup })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_plan() {
+        let root = Path::new("/tmp/root");
+        let mut plan = MountPlan::new(root, Path::new("/srv/mc/build")).unwrap();
+
+        plan.add(root, Path::new("/srv/mc/build"), MountKind::Tmpfs)
+            .unwrap();
+        plan.add(root, Path::new("/tmp"), MountKind::Tmpfs).unwrap();
+
+        assert_eq!(plan.mounts[0].target.as_bytes(), b"/tmp/root/tmp");
+        assert_eq!(
+            plan.mounts[1]
+                .parents
+                .iter()
+                .map(|p| p.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["/tmp/root/srv", "/tmp/root/srv/mc"]
+        );
+    }
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(1.5), "150000 100000");
+        assert_eq!(cpu_max(0.0), "1000 100000");
+    }
+
+    /// Whether this process may create user namespaces
+    fn user_namespaces_available() -> bool {
+        let mut command = process::Command::new("true");
+
+        unsafe {
+            command.pre_exec(|| check(libc::unshare(libc::CLONE_NEWUSER)));
+        }
+
+        command.status().is_ok_and(|s| s.success())
+    }
+
+    #[tokio::test]
+    async fn test_isolation() {
+        if !user_namespaces_available() {
+            eprintln!("Skipping sandbox test, user namespaces are unavailable");
+            return;
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let secret = temp.path().join("secret.txt");
+        fs::write(&secret, "do not read").unwrap();
+
+        let store = VolkanicStore::new_custom_root(temp.path().join("store")).await;
+
+        for p in [&store.build_path, &store.runtime_path, &store.temp_path] {
+            fs::create_dir_all(p).unwrap();
+        }
+
+        let mut command = process::Command::new("/bin/sh");
+        command
+            .args([
+                "-c",
+                "cat \"$1\"; for p in /proc/[0-9]*; do echo \"pid ${p#/proc/}\"; done; exit 3",
+            ])
+            .arg("sh")
+            .arg(&secret);
+
+        let _sandbox = Sandbox::apply(
+            &mut command,
+            &store,
+            &ResourceLimits::default(),
+            &SandboxOptions::default(),
+        )
+        .unwrap();
+
+        let output = command.output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert!(!stdout.contains("do not read"));
+        // Only the sandbox's own process is visible, and it's PID 1
+        assert_eq!(stdout.trim(), "pid 1");
+        // The exit status is passed on from inside the PID namespace
+        assert_eq!(output.status.code(), Some(3));
+    }
+}
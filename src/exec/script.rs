@@ -1,6 +1,8 @@
-use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use super::BuildExecInfo;
+use super::{BuildExecInfo, RestartOptions, RestartPolicy, ShutdownOptions};
+use crate::vkstore::VolkanicStore;
 
 const BASH_SHEBANG: &str = "#!/usr/bin/env bash";
 const BATCH_ECHO_OFF: &str = "@echo off";
@@ -9,21 +11,257 @@ const BATCH_ECHO_OFF: &str = "@echo off";
 pub enum ExecScriptType {
     Bash,
     Batch,
+    /// systemd service unit
+    Systemd,
 }
 
-/// Creates a Bash script from `BuildExecInfo`
-pub async fn to_script<P: AsRef<Path>>(
+/// How the systemd service stops the server
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum StopVia {
+    /// Start the server with `vkconstruct run`, which sends the stop command
+    /// to the console on SIGTERM
+    #[default]
+    Console,
+    /// Start the server directly and send the stop command with `vkconstruct rcon`
+    Rcon,
+}
+
+/// Absolute paths used in the systemd unit
+#[derive(Debug, Clone)]
+struct UnitPaths {
+    vkconstruct: PathBuf,
+    store: PathBuf,
+    build: PathBuf,
+    runtime_exec: PathBuf,
+    /// Directory the build was made from, which `exec-path` is relative to
+    working_dir: PathBuf,
+}
+
+impl UnitPaths {
+    fn resolve(store: &VolkanicStore, exec_info: &BuildExecInfo) -> std::io::Result<Self> {
+        Ok(Self {
+            vkconstruct: std::env::current_exe()?,
+            store: store.path.canonicalize()?,
+            build: store.build_path.canonicalize()?,
+            runtime_exec: exec_info.exec_path.canonicalize()?,
+            working_dir: std::env::current_dir()?,
+        })
+    }
+}
+
+/// Settings for the systemd unit
+#[derive(Debug, Clone, Default)]
+pub struct UnitOptions {
+    pub description: String,
+    /// User to run the server as
+    pub user: Option<String>,
+    pub stop_via: StopVia,
+    pub restart: RestartOptions,
+    pub shutdown: ShutdownOptions,
+}
+
+/// Quotes an argument for a systemd command line
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        return arg;
+    }
+
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn systemd_command<I: IntoIterator<Item = S>, S: AsRef<str>>(args: I) -> String {
+    args.into_iter()
+        .map(|a| systemd_quote(a.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats a duration in whole seconds, rounding up
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Creates a systemd service unit from `BuildExecInfo`
+fn to_systemd_unit(exec_info: &BuildExecInfo, options: &UnitOptions, paths: &UnitPaths) -> String {
+    let vkconstruct = |args: &[&str]| {
+        let store = paths.store.to_string_lossy();
+        let build = paths.build.to_string_lossy();
+        let mut command = vec![
+            paths.vkconstruct.to_string_lossy().to_string(),
+            "-s".to_string(),
+            store.to_string(),
+            "-b".to_string(),
+            build.to_string(),
+        ];
+
+        command.extend(args.iter().map(|a| a.to_string()));
+
+        systemd_command(command)
+    };
+
+    let mut unit = vec![
+        "[Unit]".to_string(),
+        format!("Description={}", options.description),
+        "Wants=network-online.target".to_string(),
+        "After=network-online.target".to_string(),
+    ];
+    let restart = &options.restart;
+
+    if restart.policy != RestartPolicy::Never {
+        unit.push(format!("StartLimitIntervalSec={}", seconds(restart.window)));
+        // The first start counts towards the limit
+        unit.push(format!("StartLimitBurst={}", restart.max_restarts + 1));
+    }
+
+    unit.extend([
+        "".to_string(),
+        "[Service]".to_string(),
+        "Type=simple".to_string(),
+    ]);
+
+    if let Some(user) = &options.user {
+        unit.push(format!("User={}", user));
+    }
+
+    let stop_command = exec_info
+        .stop_command
+        .as_deref()
+        .unwrap_or(super::shutdown::DEFAULT_STOP_COMMAND);
+    let shutdown = &options.shutdown;
+
+    match options.stop_via {
+        StopVia::Console => {
+            let stop_timeout = format!("{}s", seconds(shutdown.timeout));
+            let kill_timeout = format!("{}s", seconds(shutdown.kill_timeout));
+
+            unit.push(format!(
+                "WorkingDirectory={}",
+                systemd_quote(&paths.working_dir.to_string_lossy())
+            ));
+            unit.push(format!(
+                "ExecStart={}",
+                vkconstruct(&[
+                    "run",
+                    "--stop-timeout",
+                    &stop_timeout,
+                    "--kill-timeout",
+                    &kill_timeout
+                ])
+            ));
+            // Only `run` gets SIGTERM, it stops the server itself
+            unit.push("KillMode=mixed".to_string());
+            unit.push(format!(
+                "TimeoutStopSec={}",
+                seconds(shutdown.timeout + shutdown.kill_timeout) + 5
+            ));
+        }
+        StopVia::Rcon => {
+            let mut command = vec![paths.runtime_exec.to_string_lossy().to_string()];
+            command.extend(exec_info.args.iter().cloned());
+
+            unit.push(format!(
+                "WorkingDirectory={}",
+                systemd_quote(&paths.build.to_string_lossy())
+            ));
+            unit.push(format!("ExecStart={}", systemd_command(command)));
+            // Ignore failures so the server is still terminated if RCON is down
+            unit.push(format!(
+                "ExecStop=-{}",
+                vkconstruct(&["rcon", stop_command])
+            ));
+            unit.push(
+                "ExecStop=/bin/sh -c 'while kill -0 $MAINPID 2>/dev/null; do sleep 1; done'"
+                    .to_string(),
+            );
+            unit.push(format!("TimeoutStopSec={}", seconds(shutdown.timeout)));
+        }
+    }
+
+    match restart.policy {
+        RestartPolicy::Never => unit.push("Restart=no".to_string()),
+        policy => {
+            unit.push(format!(
+                "Restart={}",
+                match policy {
+                    RestartPolicy::OnFailure => "on-failure",
+                    _ => "always",
+                }
+            ));
+            unit.push(format!("RestartSec={}", seconds(restart.delay)));
+
+            // Exponential backoff, ignored before systemd 254
+            if restart.max_delay > restart.delay && !restart.delay.is_zero() {
+                let steps = (restart.max_delay.as_secs_f64() / restart.delay.as_secs_f64())
+                    .log2()
+                    .ceil() as u64;
+
+                unit.push(format!("RestartSteps={}", steps));
+                unit.push(format!("RestartMaxDelaySec={}", seconds(restart.max_delay)));
+            }
+        }
+    }
+
+    if let Some(memory) = exec_info.limits.memory_mb {
+        unit.push(format!("MemoryMax={}M", memory));
+        unit.push("MemorySwapMax=0".to_string());
+    }
+    if let Some(cpus) = exec_info.limits.cpus {
+        unit.push(format!("CPUQuota={}%", (cpus * 100.0).round() as u64));
+    }
+
+    // `run` also creates the console socket in the store
+    let writable = match options.stop_via {
+        StopVia::Console => &paths.store,
+        StopVia::Rcon => &paths.build,
+    };
+
+    // MemoryDenyWriteExecute is left out as the JIT needs executable memory
+    unit.extend([
+        "NoNewPrivileges=yes".to_string(),
+        "PrivateTmp=yes".to_string(),
+        "PrivateDevices=yes".to_string(),
+        "ProtectSystem=strict".to_string(),
+        "ProtectHome=read-only".to_string(),
+        "ProtectKernelTunables=yes".to_string(),
+        "ProtectKernelModules=yes".to_string(),
+        "ProtectControlGroups=yes".to_string(),
+        "RestrictSUIDSGID=yes".to_string(),
+        "LockPersonality=yes".to_string(),
+        format!(
+            "ReadWritePaths={}",
+            systemd_quote(&writable.to_string_lossy())
+        ),
+        "".to_string(),
+        "[Install]".to_string(),
+        "WantedBy=multi-user.target".to_string(),
+    ]);
+
+    unit.join("\n")
+}
+
+/// Creates a start script or service unit from `BuildExecInfo`. `unit` only
+/// applies to `ExecScriptType::Systemd`
+pub async fn to_script(
     exec_info: BuildExecInfo,
-    build_path: P,
+    store: &VolkanicStore,
     format: ExecScriptType,
-) -> String {
-    match format {
+    unit: &UnitOptions,
+) -> std::io::Result<String> {
+    let build_path = &store.build_path;
+
+    Ok(match format {
         ExecScriptType::Bash => {
             format!(
                 "{}\n\nexport JDK_PATH=$(realpath {})\ncd {} && exec $JDK_PATH {}",
                 BASH_SHEBANG,
                 exec_info.exec_path.to_string_lossy(),
-                build_path.as_ref().to_string_lossy(),
+                build_path.to_string_lossy(),
                 exec_info.args.join(" "),
             )
         }
@@ -32,20 +270,22 @@ pub async fn to_script<P: AsRef<Path>>(
                 "{}\n\nset \"JDK_PATH=%~dp0\\{}\"\ncd {}\n\"%JDK_PATH%\" {}",
                 BATCH_ECHO_OFF,
                 exec_info.exec_path.to_string_lossy(),
-                build_path.as_ref().to_string_lossy(),
+                build_path.to_string_lossy(),
                 exec_info.args.join(" "),
             )
         }
-    }
+        ExecScriptType::Systemd => {
+            to_systemd_unit(&exec_info, unit, &UnitPaths::resolve(store, &exec_info)?)
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use super::to_script;
+    use super::*;
 
-    #[tokio::test]
-    async fn test_bash() {
-        let exec_info = super::BuildExecInfo {
+    fn exec_info() -> BuildExecInfo {
+        BuildExecInfo {
             arch: crate::hostinfo::Arch::Amd64,
             os: crate::hostinfo::Os::Linux,
             exec_path: std::path::PathBuf::from(".volkanic/runtime/java"),
@@ -55,15 +295,75 @@ mod tests {
                 "-jar".to_string(),
                 "server.jar".to_string(),
             ],
-        };
+            limits: Default::default(),
+            stop_command: None,
+        }
+    }
 
+    #[tokio::test]
+    async fn test_bash() {
         let script = to_script(
-            exec_info,
-            std::path::PathBuf::from(".volkanic/build"),
-            crate::exec::script::ExecScriptType::Bash,
+            exec_info(),
+            &VolkanicStore::new()
+                .await
+                .override_build(".volkanic/build")
+                .await,
+            ExecScriptType::Bash,
+            &UnitOptions::default(),
         )
-        .await;
+        .await
+        .unwrap();
 
         assert_eq!(script, "#!/usr/bin/env bash\n\nexport JDK_PATH=$(realpath .volkanic/runtime/java)\ncd .volkanic/build && exec $JDK_PATH -Xms512M -Xmx1024M -jar server.jar");
     }
+
+    #[test]
+    fn test_systemd() {
+        let mut exec_info = exec_info();
+
+        exec_info.limits.memory_mb = Some(1280);
+        exec_info.args.push("--motd=50% off".to_string());
+
+        let paths = UnitPaths {
+            vkconstruct: PathBuf::from("/usr/bin/vkconstruct"),
+            store: PathBuf::from("/srv/mc/.volkanic"),
+            build: PathBuf::from("/srv/mc/.volkanic/build"),
+            runtime_exec: PathBuf::from("/srv/mc/.volkanic/runtime/bin/java"),
+            working_dir: PathBuf::from("/srv/mc"),
+        };
+        let mut options = UnitOptions {
+            description: "Vanilla".to_string(),
+            user: Some("minecraft".to_string()),
+            stop_via: StopVia::Rcon,
+            ..Default::default()
+        };
+        options.restart.policy = RestartPolicy::OnFailure;
+
+        let unit = to_systemd_unit(&exec_info, &options, &paths);
+        let lines: Vec<_> = unit.lines().collect();
+
+        for line in [
+            "User=minecraft",
+            "WorkingDirectory=/srv/mc/.volkanic/build",
+            "ExecStart=/srv/mc/.volkanic/runtime/bin/java -Xms512M -Xmx1024M -jar server.jar \"--motd=50%% off\"",
+            "ExecStop=-/usr/bin/vkconstruct -s /srv/mc/.volkanic -b /srv/mc/.volkanic/build rcon stop",
+            "Restart=on-failure",
+            "StartLimitBurst=6",
+            "RestartSteps=6",
+            "MemoryMax=1280M",
+            "ReadWritePaths=/srv/mc/.volkanic/build",
+        ] {
+            assert!(lines.contains(&line), "missing {:?} in:\n{}", line, unit);
+        }
+
+        options.stop_via = StopVia::Console;
+
+        let unit = to_systemd_unit(&exec_info, &options, &paths);
+
+        assert!(unit.contains(
+            "ExecStart=/usr/bin/vkconstruct -s /srv/mc/.volkanic -b /srv/mc/.volkanic/build run --stop-timeout 60s --kill-timeout 10s\n"
+        ));
+        assert!(unit.contains("WorkingDirectory=/srv/mc\n"));
+        assert!(!unit.contains("ExecStop="));
+    }
 }
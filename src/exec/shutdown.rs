@@ -0,0 +1,189 @@
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin};
+use tokio::signal;
+use tracing::{debug, info, warn};
+
+use crate::duration::format_duration;
+
+/// Console command used when the runtime doesn't specify one
+pub const DEFAULT_STOP_COMMAND: &str = "stop";
+
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+    /// Time to wait for the server after sending the stop command
+    pub timeout: Duration,
+    /// Time to wait after SIGTERM before killing the server
+    pub kill_timeout: Duration,
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(60),
+            kill_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// How a requested shutdown ended
+#[derive(Debug)]
+pub enum Shutdown {
+    /// The server exited after the stop command
+    Graceful(ExitStatus),
+    /// The server exited after SIGTERM
+    Terminated,
+    /// The server had to be killed
+    Killed,
+}
+
+/// Listens for SIGINT and SIGTERM
+pub struct ShutdownSignals {
+    #[cfg(unix)]
+    terminate: signal::unix::Signal,
+}
+
+impl ShutdownSignals {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            terminate: signal::unix::signal(signal::unix::SignalKind::terminate())?,
+        })
+    }
+    /// Waits for the next shutdown signal
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = self.terminate.recv() => {}
+        }
+        #[cfg(not(unix))]
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Sends a line to the server console
+pub async fn write_line(stdin: &mut ChildStdin, line: &str) -> io::Result<()> {
+    stdin.write_all(format!("{}\n", line).as_bytes()).await?;
+    stdin.flush().await
+}
+
+/// Sends SIGTERM to the server's process group
+#[cfg(unix)]
+fn terminate(child: &Child) {
+    if let Some(pid) = child.id() {
+        // The server leads its own process group, see `run`
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) };
+    }
+}
+
+/// Stops the server with its console command, escalating to SIGTERM and then
+/// SIGKILL if it doesn't exit in time. Another signal skips the wait.
+pub async fn stop_server(
+    child: &mut Child,
+    stdin: Option<&mut ChildStdin>,
+    stop_command: &str,
+    options: &ShutdownOptions,
+    signals: &mut ShutdownSignals,
+) -> io::Result<Shutdown> {
+    info!(
+        "Sending \"{}\" to the server, waiting up to {}",
+        stop_command,
+        format_duration(options.timeout)
+    );
+
+    match stdin {
+        Some(stdin) => {
+            if let Err(e) = write_line(stdin, stop_command).await {
+                debug!("Unable to write stop command: {}", e);
+            }
+        }
+        None => warn!("Server console is unavailable"),
+    }
+
+    tokio::select! {
+        status = child.wait() => return Ok(Shutdown::Graceful(status?)),
+        _ = tokio::time::sleep(options.timeout) => warn!("Server didn't stop in time"),
+        _ = signals.recv() => warn!("Received another signal, not waiting"),
+    }
+
+    #[cfg(unix)]
+    {
+        warn!("Sending SIGTERM to the server");
+        terminate(child);
+
+        tokio::select! {
+            status = child.wait() => {
+                status?;
+                return Ok(Shutdown::Terminated);
+            }
+            _ = tokio::time::sleep(options.kill_timeout) => {}
+            _ = signals.recv() => {}
+        }
+    }
+
+    warn!("Killing the server");
+    child.kill().await?;
+
+    Ok(Shutdown::Killed)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::super::run::ExecutionError;
+    use super::*;
+
+    fn spawn(script: &str) -> Child {
+        tokio::process::Command::new("sh")
+            .args(["-c", script])
+            .stdin(std::process::Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap()
+    }
+
+    async fn stop(script: &str) -> Shutdown {
+        let mut child = spawn(script);
+        let mut stdin = child.stdin.take();
+        let options = ShutdownOptions {
+            timeout: Duration::from_millis(300),
+            kill_timeout: Duration::from_millis(300),
+        };
+
+        stop_server(
+            &mut child,
+            stdin.as_mut(),
+            DEFAULT_STOP_COMMAND,
+            &options,
+            &mut ShutdownSignals::new().unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_graceful_stop() {
+        match stop("read line; [ \"$line\" = stop ]").await {
+            Shutdown::Graceful(status) => assert!(status.success()),
+            s => panic!("expected a graceful stop, got {:?}", s),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_escalation() {
+        // Ignores the console, but not SIGTERM
+        let terminated = stop("while :; do sleep 0.1; done").await;
+        assert!(matches!(terminated, Shutdown::Terminated));
+        assert_eq!(ExecutionError::Terminated.exit_code(), 143);
+
+        let killed = stop("trap '' TERM; while :; do sleep 0.1; done").await;
+        assert!(matches!(killed, Shutdown::Killed));
+        assert_eq!(ExecutionError::Killed.exit_code(), 137);
+    }
+}
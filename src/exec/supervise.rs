@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// When the server should be started again after exiting
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum RestartPolicy {
+    /// Never restart the server
+    #[default]
+    Never,
+    /// Restart only after crashes, not after a clean `stop`
+    OnFailure,
+    /// Restart after every exit
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct RestartOptions {
+    pub policy: RestartPolicy,
+    /// Restarts allowed within `window` before giving up
+    pub max_restarts: usize,
+    pub window: Duration,
+    /// Delay before the first restart, doubled for each recent restart
+    pub delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RestartOptions {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::Never,
+            max_restarts: 5,
+            window: Duration::from_secs(10 * 60),
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How the server process ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    /// Exit code 0, e.g. after the `stop` command
+    Clean,
+    /// Non-zero exit code, or `None` if killed by a signal
+    Crashed(Option<i32>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    Stop,
+    Restart(Duration),
+    /// Too many restarts within the window
+    CrashLoop,
+}
+
+/// Applies the restart policy, backing off and detecting crash loops
+#[derive(Debug)]
+pub struct Supervisor {
+    options: RestartOptions,
+    restarts: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(options: RestartOptions) -> Self {
+        Self {
+            options,
+            restarts: VecDeque::new(),
+        }
+    }
+    /// Decides what to do after the server exited at `now`
+    pub fn next(&mut self, exit: Exit, now: Instant) -> Decision {
+        let restart = match self.options.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit != Exit::Clean,
+            RestartPolicy::Always => true,
+        };
+
+        if !restart {
+            return Decision::Stop;
+        }
+
+        while let Some(t) = self.restarts.front() {
+            if now.duration_since(*t) > self.options.window {
+                self.restarts.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.restarts.len() >= self.options.max_restarts {
+            return Decision::CrashLoop;
+        }
+
+        let delay = self
+            .options
+            .delay
+            .saturating_mul(2u32.saturating_pow(self.restarts.len() as u32))
+            .min(self.options.max_delay);
+
+        self.restarts.push_back(now);
+
+        Decision::Restart(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supervisor() {
+        let options = RestartOptions {
+            policy: RestartPolicy::OnFailure,
+            max_restarts: 3,
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+            ..Default::default()
+        };
+        let mut supervisor = Supervisor::new(options.clone());
+        let start = Instant::now();
+
+        assert_eq!(supervisor.next(Exit::Clean, start), Decision::Stop);
+
+        let crash = Exit::Crashed(Some(1));
+
+        assert_eq!(
+            supervisor.next(crash, start),
+            Decision::Restart(Duration::from_secs(1))
+        );
+        assert_eq!(
+            supervisor.next(crash, start),
+            Decision::Restart(Duration::from_secs(2))
+        );
+        assert_eq!(
+            supervisor.next(crash, start),
+            Decision::Restart(Duration::from_secs(3))
+        );
+        assert_eq!(supervisor.next(crash, start), Decision::CrashLoop);
+
+        // Restarts outside the window are forgotten
+        let later = start + options.window + Duration::from_secs(1);
+
+        assert_eq!(
+            supervisor.next(Exit::Crashed(None), later),
+            Decision::Restart(Duration::from_secs(1))
+        );
+    }
+}
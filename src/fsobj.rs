@@ -75,3 +75,69 @@ pub async fn create_ancestors<P: AsRef<path::Path>>(path: P) -> Result<(), Creat
         ))
     }
 }
+
+#[derive(thiserror::Error, Debug)]
+#[error("Path leaves \"{base}\": {path}")]
+pub struct UnsafePathError {
+    pub base: path::PathBuf,
+    pub path: path::PathBuf,
+}
+
+/// Joins a relative path from a template onto `base`, making sure the
+/// result stays inside `base`
+///
+/// Absolute paths and `..` components are rejected, as are paths leading
+/// through links to outside of `base`.
+pub fn safe_join<B: AsRef<path::Path>, P: AsRef<path::Path>>(
+    base: B,
+    path: P,
+) -> Result<path::PathBuf, UnsafePathError> {
+    let (base, path) = (base.as_ref(), path.as_ref());
+
+    let unsafe_path = || UnsafePathError {
+        base: base.to_path_buf(),
+        path: path.to_path_buf(),
+    };
+
+    if !path
+        .components()
+        .all(|c| matches!(c, path::Component::Normal(_) | path::Component::CurDir))
+    {
+        return Err(unsafe_path());
+    }
+
+    let joined = base.join(path);
+
+    // The closest existing ancestor must really be inside `base`
+    if let Ok(real_base) = base.canonicalize() {
+        // Dangling links count as existing, as writing to them would
+        // create their target
+        let existing = joined
+            .ancestors()
+            .find(|p| p.symlink_metadata().is_ok())
+            .unwrap_or(base);
+
+        match existing.canonicalize() {
+            Ok(real) if real.starts_with(&real_base) => {}
+            _ => return Err(unsafe_path()),
+        }
+    }
+
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_join() {
+        let base = std::env::temp_dir();
+
+        assert!(safe_join(&base, "plugins/a.jar").is_ok());
+        assert!(safe_join(&base, ".").is_ok());
+        assert!(safe_join(&base, "../a.jar").is_err());
+        assert!(safe_join(&base, "plugins/../../a.jar").is_err());
+        assert!(safe_join(&base, "/etc/passwd").is_err());
+    }
+}
@@ -129,3 +129,20 @@ impl Display for Arch {
         })
     }
 }
+
+/// Total physical memory of the host in bytes
+pub async fn total_memory() -> u64 {
+    let sys = sysinfo::System::new_with_specifics(
+        sysinfo::RefreshKind::nothing()
+            .with_memory(sysinfo::MemoryRefreshKind::nothing().with_ram()),
+    );
+
+    sys.total_memory()
+}
+
+/// Number of logical CPUs available to the process
+pub async fn cpu_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
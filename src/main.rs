@@ -5,9 +5,12 @@ use std::path;
 use tracing::{debug, error, info};
 
 mod build;
+mod duration;
 mod exec;
 mod fsobj;
 mod hostinfo;
+mod netconf;
+mod rcon;
 mod resources;
 mod saveable;
 mod template;
@@ -27,6 +30,21 @@ struct Args {
     /// Override downloads directory
     #[arg(short = 'd', long)]
     override_downloads_dir: Option<path::PathBuf>,
+    /// Number of retries after a failed download
+    #[arg(long, default_value_t = netconf::DEFAULT_RETRIES)]
+    download_retries: u32,
+    /// Seconds to wait for a connection before giving up
+    #[arg(long, default_value_t = netconf::DEFAULT_CONNECT_TIMEOUT)]
+    connect_timeout: u64,
+    /// Seconds to wait between reads of a response before giving up
+    #[arg(long, default_value_t = netconf::DEFAULT_READ_TIMEOUT)]
+    read_timeout: u64,
+    /// Network configuration file with proxy, certificates and credentials
+    #[arg(long)]
+    net_config: Option<path::PathBuf>,
+    /// Download cache shared between stores (defaults to "VKCONSTRUCT_CACHE_DIR")
+    #[arg(long)]
+    cache_dir: Option<path::PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -46,6 +64,9 @@ enum Command {
         /// Include a importable archive in the build
         #[arg(short = 'i', long)]
         import_save: Option<path::PathBuf>,
+        /// Override include directory (defaults to ".vkinclude" next to the template)
+        #[arg(long)]
+        include_dir: Option<path::PathBuf>,
         /// Disable verification for all files
         #[arg(long)]
         no_verify: bool,
@@ -58,14 +79,62 @@ enum Command {
     },
     /// Parse a template at the given path
     Check { path: path::PathBuf },
-    /// Runs the build in the current directory. Only use for testing with trusted templates. Do not use for execution in production.
-    Run,
+    /// Runs the build in the current directory. Only use for testing with trusted templates unless sandboxed.
+    Run {
+        /// Run in a Linux sandbox exposing only the build and runtime
+        /// directories, with resource limits from the template
+        #[arg(long)]
+        sandbox: bool,
+        /// Delegated cgroup v2 directory to create the sandbox cgroup in
+        #[arg(long, requires = "sandbox")]
+        cgroup_parent: Option<path::PathBuf>,
+        #[command(flatten)]
+        restart: RestartArgs,
+        #[command(flatten)]
+        shutdown: ShutdownArgs,
+    },
+    /// Attach to the console of a server started with `run`. Press Ctrl+D to detach
+    Console {
+        /// Only view output, leaving commands to another client
+        #[arg(long)]
+        read_only: bool,
+    },
+    /// Send a command to a running server over RCON. Starts an interactive
+    /// prompt if no command is given
+    Rcon {
+        /// Command to run
+        #[arg(trailing_var_arg = true)]
+        command: Vec<String>,
+        /// Server address
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+        /// RCON port. Defaults to `rcon.port` in `server.properties`
+        #[arg(long)]
+        port: Option<u16>,
+        /// RCON password. Defaults to `rcon.password` in `server.properties`
+        /// or the VKCONSTRUCT_RCON_PASSWORD environment variable
+        #[arg(long)]
+        password: Option<String>,
+        /// Keep Minecraft formatting codes in responses
+        #[arg(long)]
+        raw: bool,
+    },
     /// Template management commands
     #[command(subcommand)]
     Template(TemplateCommand),
-    /// Create a Bash script from the execution information of an existing build
+    /// Create a start script or systemd unit from the execution information of an existing build
     ExecScript {
         format: exec::script::ExecScriptType,
+        /// How the systemd unit stops the server
+        #[arg(long, value_enum, default_value_t = exec::script::StopVia::Console)]
+        stop_via: exec::script::StopVia,
+        /// User the systemd unit runs the server as
+        #[arg(long)]
+        user: Option<String>,
+        #[command(flatten)]
+        restart: RestartArgs,
+        #[command(flatten)]
+        shutdown: ShutdownArgs,
     },
     Export {
         /// Path to write export archive
@@ -73,16 +142,101 @@ enum Command {
     },
     /// Clear downloads and temporary files
     Clean,
+    /// Shared download cache commands
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+/// Restart policy shared by `run` and the systemd unit
+#[derive(Debug, Clone, clap::Args)]
+struct RestartArgs {
+    /// When to restart the server after it exits
+    #[arg(long, value_enum, default_value_t = exec::RestartPolicy::Never)]
+    restart: exec::RestartPolicy,
+    /// Restarts allowed within the restart window before giving up
+    #[arg(long, default_value_t = 5)]
+    max_restarts: usize,
+    /// Window for crash-loop detection (e.g. "10m")
+    #[arg(long, default_value = "10m", value_parser = duration::parse_duration)]
+    restart_window: std::time::Duration,
+    /// Delay before the first restart, doubled for each recent restart
+    #[arg(long, default_value = "1s", value_parser = duration::parse_duration)]
+    restart_delay: std::time::Duration,
+    /// Upper bound for the restart delay
+    #[arg(long, default_value = "1m", value_parser = duration::parse_duration)]
+    max_restart_delay: std::time::Duration,
+}
+
+impl RestartArgs {
+    fn options(&self) -> exec::RestartOptions {
+        exec::RestartOptions {
+            policy: self.restart,
+            max_restarts: self.max_restarts,
+            window: self.restart_window,
+            delay: self.restart_delay,
+            max_delay: self.max_restart_delay,
+        }
+    }
+}
+
+/// Shutdown timeouts shared by `run` and the systemd unit
+#[derive(Debug, Clone, clap::Args)]
+struct ShutdownArgs {
+    /// Time to wait for the server after sending the stop command on
+    /// SIGINT/SIGTERM
+    #[arg(long, default_value = "1m", value_parser = duration::parse_duration)]
+    stop_timeout: std::time::Duration,
+    /// Time to wait after SIGTERM before killing the server
+    #[arg(long, default_value = "10s", value_parser = duration::parse_duration)]
+    kill_timeout: std::time::Duration,
+}
+
+impl ShutdownArgs {
+    fn options(&self) -> exec::ShutdownOptions {
+        exec::ShutdownOptions {
+            timeout: self.stop_timeout,
+            kill_timeout: self.kill_timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum CacheCommand {
+    /// List cached files, most recently used first
+    List,
+    /// Remove cached files which are old or over the size limit
+    Gc {
+        /// Remove least recently used files until the cache fits (e.g. "10G")
+        #[arg(long, value_parser = vkstore::cache::parse_size)]
+        max_size: Option<u64>,
+        /// Remove files unused for longer than this (e.g. "30d")
+        #[arg(long, value_parser = duration::parse_duration)]
+        older_than: Option<std::time::Duration>,
+    },
+    /// Check cached files against their hashes, removing corrupted files
+    Verify,
 }
 
 #[derive(Debug, Clone, Subcommand)]
 enum TemplateCommand {
     /// Moves all external "include" files into template
-    Embed { path: path::PathBuf },
+    Embed {
+        path: path::PathBuf,
+        /// Override include directory (defaults to ".vkinclude" next to the template)
+        #[arg(long)]
+        include_dir: Option<path::PathBuf>,
+    },
     /// Prints a basic template
     Create,
     /// Generate a JSON schema for templates
     GenerateSchema,
+    /// Create a template from a Modrinth modpack (.mrpack) for vanilla or
+    /// Fabric
+    ImportMrpack {
+        path: path::PathBuf,
+        /// Directory to write the template and overrides to
+        output: path::PathBuf,
+    },
     /// Overlay management commands
     #[command(subcommand)]
     Overlay(OverlayCommand),
@@ -121,9 +275,20 @@ async fn init_log() {
 async fn main() {
     let args = Args::parse();
 
+    netconf::NetConfig {
+        retries: args.download_retries,
+        connect_timeout: std::time::Duration::from_secs(args.connect_timeout),
+        read_timeout: std::time::Duration::from_secs(args.read_timeout),
+        ..Default::default()
+    }
+    .init(args.net_config);
+
     let store_d = args.override_store_dir;
     let build_d = args.override_build_dir;
     let downloads_d = args.override_downloads_dir;
+    let cache_d = args
+        .cache_dir
+        .or_else(|| std::env::var_os(vkstore::cache::CACHE_DIR_ENV).map(path::PathBuf::from));
 
     match args.command {
         Command::Build {
@@ -133,6 +298,7 @@ async fn main() {
             user_vars,
             additional_jvm_args,
             import_save,
+            include_dir,
             no_verify,
             force_jdk_distribution,
             preferred_distributions,
@@ -151,13 +317,13 @@ async fn main() {
                     }
                     Err(e) => {
                         error!("Failed to parse overlay: {}", e);
-                        
+
                         std::process::exit(1);
                     }
                 }
             }
 
-            let store = vkstore_init(store_d, build_d, downloads_d).await;
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
 
             match build::build(
                 template,
@@ -165,6 +331,7 @@ async fn main() {
                 store.clone(),
                 force,
                 user_vars,
+                include_dir,
                 additional_jvm_args,
                 no_verify,
                 force_jdk_distribution,
@@ -200,21 +367,87 @@ async fn main() {
 
             parse_template(path).await;
         }
-        Command::Run => {
+        Command::Run {
+            sandbox,
+            cgroup_parent,
+            restart,
+            shutdown,
+        } => {
             init_log().await;
 
-            let store = vkstore_init(store_d, build_d, downloads_d).await;
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
+
+            let options = exec::RunOptions {
+                sandbox: sandbox.then_some(exec::SandboxOptions { cgroup_parent }),
+                restart: restart.options(),
+                shutdown: shutdown.options(),
+            };
 
-            match exec::run(&store).await {
+            match exec::run(&store, &options).await {
                 Ok(()) => {}
                 Err(e) => {
                     error!("Failed to execute template: {}", e);
-                    std::process::exit(1);
+                    std::process::exit(e.exit_code());
+                }
+            };
+        }
+        Command::Console { read_only } => {
+            init_log().await;
+
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
+
+            let mode = if read_only {
+                exec::console::Mode::Read
+            } else {
+                exec::console::Mode::Write
+            };
+
+            #[cfg(unix)]
+            let result = exec::console::attach(&exec::console::socket_path(&store), mode).await;
+            #[cfg(not(unix))]
+            let result = {
+                let _ = (store, mode);
+                Err::<(), _>(exec::console::ConsoleError::Unsupported)
+            };
+
+            if let Err(e) = result {
+                error!("Failed to attach to console: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Command::Rcon {
+            command,
+            host,
+            port,
+            password,
+            raw,
+        } => {
+            init_log().await;
+
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
+            let password = password.or_else(|| std::env::var(rcon::PASSWORD_ENV).ok());
+
+            let result = async {
+                let config = rcon::RconConfig::from_build(&store, host, port, password).await?;
+                let mut client = rcon::Rcon::connect(&config).await?;
+
+                if command.is_empty() {
+                    rcon::repl(&mut client, raw).await
+                } else {
+                    let response = client.command(&command.join(" ")).await?;
+
+                    rcon::print_response(&response, raw);
+                    Ok(())
                 }
             };
+
+            if let Err(e) = result.await {
+                error!("RCON failed: {}", e);
+                std::process::exit(1);
+            }
         }
         Command::Template(command) => match command {
-            TemplateCommand::Embed { path } => {
+            TemplateCommand::Embed { path, include_dir } => {
                 let template = match template::Template::import(path).await {
                     Ok(template) => template,
                     Err(e) => {
@@ -225,7 +458,7 @@ async fn main() {
                     }
                 };
 
-                match template::manage::embed(template).await {
+                match template::manage::embed(template, include_dir.as_deref()).await {
                     Ok(t) => {
                         println!(
                             "{}",
@@ -267,6 +500,21 @@ async fn main() {
 
                 println!("{}", serde_jsonc::to_string(&schema).unwrap());
             }
+            TemplateCommand::ImportMrpack { path, output } => {
+                init_log().await;
+
+                match template::modpack::mrpack::import(path, &output).await {
+                    Ok(t) => info!(
+                        "Template \"{}\" written to \"{}\"",
+                        t.name,
+                        output.to_string_lossy()
+                    ),
+                    Err(e) => {
+                        error!("Failed to import modpack: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
             TemplateCommand::Overlay(command) => match command {
                 OverlayCommand::Create => {
                     println!(
@@ -289,8 +537,14 @@ async fn main() {
                 }
             },
         },
-        Command::ExecScript { format } => {
-            let store = vkstore_init(store_d, build_d, downloads_d).await;
+        Command::ExecScript {
+            format,
+            stop_via,
+            user,
+            restart,
+            shutdown,
+        } => {
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
 
             if !build::BuildInfo::exists(&store).await {
                 init_log().await;
@@ -319,15 +573,28 @@ async fn main() {
                 }
             };
 
-            println!(
-                "{}",
-                exec::script::to_script(exec_info, store.build_path, format).await
-            );
+            let unit = exec::script::UnitOptions {
+                description: build_info.template.name.clone(),
+                user,
+                stop_via,
+                restart: restart.options(),
+                shutdown: shutdown.options(),
+            };
+
+            match exec::script::to_script(exec_info, &store, format, &unit).await {
+                Ok(script) => println!("{}", script),
+                Err(e) => {
+                    init_log().await;
+
+                    error!("Failed to resolve paths for the script: {}", e);
+                    std::process::exit(1);
+                }
+            }
         }
         Command::Clean => {
             init_log().await;
 
-            let store = vkstore_init(store_d, build_d, downloads_d).await;
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
 
             match vkstore::VolkanicStore::clear_downloads(&store).await {
                 Ok(()) => {}
@@ -340,7 +607,7 @@ async fn main() {
         Command::Export { path } => {
             init_log().await;
 
-            let store = vkstore_init(store_d, build_d, downloads_d).await;
+            let store = vkstore_init(store_d, build_d, downloads_d, cache_d).await;
 
             let build_info = match build::BuildInfo::get(&store).await {
                 Ok(build_info) => build_info,
@@ -371,6 +638,77 @@ async fn main() {
 
             export.export(path).await.unwrap();
         }
+        Command::Cache(command) => {
+            init_log().await;
+
+            let cache = match cache_d {
+                Some(c) => vkstore::Cache::new(c),
+                None => {
+                    error!(
+                        "No shared cache configured (use \"--cache-dir\" or \"{}\")",
+                        vkstore::cache::CACHE_DIR_ENV
+                    );
+                    std::process::exit(1);
+                }
+            };
+
+            match command {
+                CacheCommand::List => {
+                    let mut entries = match cache.entries().await {
+                        Ok(entries) => entries,
+                        Err(e) => {
+                            error!("Failed to read cache: {}", e);
+                            std::process::exit(1);
+                        }
+                    };
+
+                    entries.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+
+                    for e in &entries {
+                        println!(
+                            "{}  {:>10}  {:>4} ago  {} build(s)  {}",
+                            &e.sha512[..16],
+                            vkstore::cache::format_size(e.size),
+                            duration::format_duration(e.age()),
+                            e.builds.len(),
+                            e.urls.first().map(String::as_str).unwrap_or_default()
+                        );
+                    }
+
+                    println!(
+                        "{} file(s), {}",
+                        entries.len(),
+                        vkstore::cache::format_size(entries.iter().map(|e| e.size).sum())
+                    );
+                }
+                CacheCommand::Gc {
+                    max_size,
+                    older_than,
+                } => match cache.gc(max_size, older_than).await {
+                    Ok(summary) => info!(
+                        "Removed {} file(s), freeing {} ({} remaining)",
+                        summary.removed,
+                        vkstore::cache::format_size(summary.freed),
+                        vkstore::cache::format_size(summary.remaining)
+                    ),
+                    Err(e) => {
+                        error!("Failed to collect garbage: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+                CacheCommand::Verify => match cache.verify().await {
+                    Ok(corrupted) if corrupted.is_empty() => info!("All cached files are valid"),
+                    Ok(corrupted) => {
+                        error!("Removed {} corrupted file(s)", corrupted.len());
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        error!("Failed to verify cache: {}", e);
+                        std::process::exit(1);
+                    }
+                },
+            }
+        }
     }
 }
 
@@ -378,6 +716,7 @@ async fn vkstore_init<S: AsRef<path::Path>>(
     store_dir: Option<S>,
     build_dir: Option<path::PathBuf>,
     downloads_dir: Option<path::PathBuf>,
+    cache_dir: Option<path::PathBuf>,
 ) -> vkstore::VolkanicStore {
     let mut store = match store_dir {
         Some(s) => vkstore::VolkanicStore::new_custom_root(s).await,
@@ -392,6 +731,10 @@ async fn vkstore_init<S: AsRef<path::Path>>(
         store = store.override_downloads(d).await;
     }
 
+    if let Some(c) = cache_dir {
+        store = store.override_cache(c).await;
+    }
+
     match store.init().await {
         Ok(store) => store,
         Err(e) => {
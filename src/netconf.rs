@@ -0,0 +1,562 @@
+use base64::Engine;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{redirect, Certificate, Client, NoProxy, Proxy, RequestBuilder, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Default number of retries after a failed download
+pub const DEFAULT_RETRIES: u32 = 3;
+/// Default time to wait for a connection, in seconds
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+/// Default time to wait between reads of a response, in seconds
+pub const DEFAULT_READ_TIMEOUT: u64 = 60;
+
+/// Environment variable with the path of the network configuration file
+pub const CONFIG_ENV: &str = "VKCONSTRUCT_NET_CONFIG";
+/// Environment variable with a proxy URL for all requests
+pub const PROXY_ENV: &str = "VKCONSTRUCT_PROXY";
+/// Environment variable with a PEM file of additional trusted certificates
+pub const CA_CERT_ENV: &str = "VKCONSTRUCT_CA_CERT";
+/// Prefix of environment variables with a bearer token for a host
+///
+/// The host is upper-cased with all other characters replaced by `_`, so the
+/// token for `repo.example.com` is read from `VKCONSTRUCT_TOKEN_REPO_EXAMPLE_COM`.
+pub const TOKEN_ENV_PREFIX: &str = "VKCONSTRUCT_TOKEN_";
+
+const CONFIG_FILE_NAME: &str = "volkanic/net.json";
+
+/// Longest delay between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Most redirects followed for a single request
+const MAX_REDIRECTS: usize = 10;
+
+/// Configuration and file path given to [`NetConfig::init`]
+static PENDING: OnceLock<(NetConfig, Option<PathBuf>)> = OnceLock::new();
+/// Configuration loaded on first use, or why it couldn't be loaded
+static CONFIG: OnceLock<Result<NetConfig, String>> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum NetConfError {
+    #[error("Failed to read \"{0}\": {1}")]
+    Filesystem(PathBuf, std::io::Error),
+    #[error("Failed to parse network configuration: {0}")]
+    Json(serde_jsonc::Error),
+    #[error("Invalid proxy: {0}")]
+    Proxy(reqwest::Error),
+    #[error("Invalid certificate in \"{0}\": {1}")]
+    Certificate(PathBuf, reqwest::Error),
+    #[error("Invalid header for host \"{0}\": {1}")]
+    InvalidHeader(String, String),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Failed to create HTTP client: {0}")]
+    Client(reqwest::Error),
+}
+
+/// Network configuration file, kept outside of templates so credentials are
+/// never shared with them
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct NetConfigFile {
+    /// HTTP(S) or SOCKS5 proxy URL for all requests
+    proxy: Option<String>,
+    /// Comma-separated hosts which bypass the proxy
+    no_proxy: Option<String>,
+    /// PEM files of additional trusted certificates, relative to the file
+    #[serde(default)]
+    ca_certificates: Vec<PathBuf>,
+    /// Authentication by host name (e.g. `repo.example.com` or
+    /// `repo.example.com:8443`)
+    #[serde(default)]
+    hosts: HashMap<String, HostAuth>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+/// Credentials sent with every request to a host
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HostAuth {
+    pub bearer: Option<String>,
+    pub basic: Option<BasicAuth>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+impl HostAuth {
+    /// Headers to send, including `Authorization`
+    pub fn header_values(&self) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = self
+            .headers
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        if let Some(token) = &self.bearer {
+            headers.push((AUTHORIZATION.to_string(), format!("Bearer {}", token)));
+        } else if let Some(basic) = &self.basic {
+            let credentials = format!(
+                "{}:{}",
+                basic.username,
+                basic.password.as_deref().unwrap_or_default()
+            );
+
+            headers.push((
+                AUTHORIZATION.to_string(),
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ),
+            ));
+        }
+
+        headers
+    }
+}
+
+/// Network configuration for all downloads and API requests
+#[derive(Clone)]
+pub struct NetConfig {
+    /// Number of retries after a failed download
+    pub retries: u32,
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    /// Proxy URL for all requests, otherwise the system proxy is used
+    pub proxy: Option<String>,
+    /// Comma-separated hosts which bypass the proxy
+    pub no_proxy: Option<String>,
+    /// Certificates trusted in addition to the built-in roots
+    pub certificates: Vec<Certificate>,
+    /// Authentication by host name
+    pub hosts: HashMap<String, HostAuth>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT),
+            proxy: None,
+            no_proxy: None,
+            certificates: vec![],
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+impl NetConfig {
+    /// Loads the proxy, certificates and authentication from the
+    /// configuration file and environment
+    ///
+    /// The file is read from `path`, `VKCONSTRUCT_NET_CONFIG` or the user's
+    /// configuration directory, in that order. Environment variables take
+    /// precedence over the file.
+    pub fn load(mut self, path: Option<&Path>) -> Result<Self, NetConfError> {
+        // Only the default file may be missing
+        let (path, required) = match path
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from))
+        {
+            Some(path) => (Some(path), true),
+            None => (default_config_path(), false),
+        };
+
+        let file = match path {
+            Some(path) if required || path.is_file() => {
+                debug!("Loading network configuration from \"{}\"", path.display());
+
+                let contents = std::fs::read_to_string(&path)
+                    .map_err(|e| NetConfError::Filesystem(path.clone(), e))?;
+                let mut file: NetConfigFile =
+                    serde_jsonc::from_str(&contents).map_err(NetConfError::Json)?;
+
+                if let Some(dir) = path.parent() {
+                    for cert in file.ca_certificates.iter_mut() {
+                        *cert = dir.join(&cert);
+                    }
+                }
+
+                file
+            }
+            _ => NetConfigFile::default(),
+        };
+
+        if let Some(url) = std::env::var(PROXY_ENV).ok().or(file.proxy) {
+            Proxy::all(&url).map_err(NetConfError::Proxy)?;
+
+            self.proxy = Some(url);
+            self.no_proxy = file.no_proxy;
+        }
+
+        let mut cert_paths = file.ca_certificates;
+
+        if let Some(path) = std::env::var_os(CA_CERT_ENV) {
+            cert_paths.push(PathBuf::from(path));
+        }
+
+        for path in cert_paths {
+            let pem =
+                std::fs::read(&path).map_err(|e| NetConfError::Filesystem(path.clone(), e))?;
+
+            self.certificates.extend(
+                Certificate::from_pem_bundle(&pem)
+                    .map_err(|e| NetConfError::Certificate(path.clone(), e))?,
+            );
+        }
+
+        for (host, auth) in &file.hosts {
+            for (name, value) in auth.header_values() {
+                HeaderName::try_from(name.as_str())
+                    .map_err(|e| NetConfError::InvalidHeader(host.clone(), e.to_string()))?;
+                HeaderValue::try_from(value)
+                    .map_err(|e| NetConfError::InvalidHeader(host.clone(), e.to_string()))?;
+            }
+        }
+
+        self.hosts = file.hosts;
+
+        Ok(self)
+    }
+    /// Sets the configuration for the rest of the process, with the file at
+    /// `path` loaded once a network operation needs it
+    ///
+    /// An invalid file therefore doesn't affect commands which don't use the
+    /// network. Has no effect if the configuration was already used.
+    pub fn init(self, path: Option<PathBuf>) {
+        let _ = PENDING.set((self, path));
+    }
+    /// Returns the process-wide configuration, loading it on first use
+    pub fn get() -> Result<&'static NetConfig, NetConfError> {
+        CONFIG
+            .get_or_init(|| {
+                let (config, path) = PENDING.get().cloned().unwrap_or_default();
+
+                config.load(path.as_deref()).map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(|e| NetConfError::Invalid(e.clone()))
+    }
+    /// Creates an HTTP client using the configuration
+    pub fn client(&self) -> Result<Client, reqwest::Error> {
+        let mut builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout);
+
+        // Unlike `Authorization`, custom headers are kept on redirects, so
+        // hosts with them may only redirect to themselves
+        let hosts = self.hosts.clone();
+
+        if hosts.values().any(|a| !a.headers.is_empty()) {
+            builder = builder.redirect(redirect::Policy::custom(move |attempt| {
+                let origin = &attempt.previous()[0];
+
+                if attempt.previous().len() > MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if host_auth(&hosts, origin).is_some_and(|a| !a.headers.is_empty())
+                    && !same_host(origin, attempt.url())
+                {
+                    let error = format!(
+                        "refusing to send custom headers for \"{}\" to \"{}\"",
+                        origin.host_str().unwrap_or_default(),
+                        attempt.url().host_str().unwrap_or_default()
+                    );
+
+                    attempt.error(error)
+                } else {
+                    attempt.follow()
+                }
+            }));
+        }
+
+        if let Some(url) = &self.proxy {
+            let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+            builder = builder.proxy(Proxy::all(url)?.no_proxy(no_proxy.or_else(NoProxy::from_env)));
+        }
+
+        for cert in &self.certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+
+        builder.build()
+    }
+    /// Delay before the given retry (starting at 1), doubling each time
+    pub fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_secs(1)
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_BACKOFF)
+    }
+    /// Git configuration applying the proxy and authentication to a URL,
+    /// passed through the environment so credentials don't appear in the
+    /// command line
+    ///
+    /// Headers are limited to the URL's host, which also isn't allowed to
+    /// redirect elsewhere if it has custom headers.
+    pub fn git_env(&self, url: &str) -> Vec<(String, String)> {
+        let mut config = vec![];
+
+        if let Some(proxy) = &self.proxy {
+            config.push(("http.proxy".to_string(), proxy.clone()));
+        }
+
+        if let (Some(auth), Ok(parsed)) = (self.auth_for(url), Url::parse(url)) {
+            let origin = parsed.origin().ascii_serialization();
+
+            for (name, value) in auth.header_values() {
+                config.push((
+                    format!("http.{}/.extraHeader", origin),
+                    format!("{}: {}", name, value),
+                ));
+            }
+
+            if !auth.headers.is_empty() {
+                config.push((
+                    format!("http.{}/.followRedirects", origin),
+                    "false".to_string(),
+                ));
+            }
+        }
+
+        let mut env = vec![("GIT_CONFIG_COUNT".to_string(), config.len().to_string())];
+
+        for (i, (key, value)) in config.into_iter().enumerate() {
+            env.push((format!("GIT_CONFIG_KEY_{}", i), key));
+            env.push((format!("GIT_CONFIG_VALUE_{}", i), value));
+        }
+
+        if let Some(no_proxy) = &self.no_proxy {
+            env.push(("no_proxy".to_string(), no_proxy.clone()));
+        }
+
+        env
+    }
+    /// Returns the authentication for a URL's host
+    ///
+    /// Hosts with a port are matched before hosts without one. Hosts missing
+    /// from the configuration file may have a bearer token in the environment.
+    pub fn auth_for(&self, url: &str) -> Option<HostAuth> {
+        let url = Url::parse(url).ok()?;
+
+        if let Some(auth) = host_auth(&self.hosts, &url) {
+            return Some(auth.clone());
+        }
+
+        std::env::var(token_env_var(url.host_str()?))
+            .ok()
+            .map(|token| HostAuth {
+                bearer: Some(token),
+                ..Default::default()
+            })
+    }
+}
+
+/// Returns the configured authentication for a URL's host, preferring hosts
+/// with a port
+fn host_auth<'a>(hosts: &'a HashMap<String, HostAuth>, url: &Url) -> Option<&'a HostAuth> {
+    let host = url.host_str()?;
+
+    url.port()
+        .and_then(|port| hosts.get(&format!("{}:{}", host, port)))
+        .or_else(|| hosts.get(host))
+}
+
+/// Whether two URLs point to the same host and port
+fn same_host(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Path of the configuration file in the user's configuration directory
+fn default_config_path() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+
+    dir.map(|d| d.join(CONFIG_FILE_NAME))
+}
+
+/// Name of the environment variable with a bearer token for a host
+fn token_env_var(host: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}{}", TOKEN_ENV_PREFIX, host)
+}
+
+/// Creates an HTTP client using the process-wide configuration
+pub fn client() -> Result<Client, NetConfError> {
+    NetConfig::get()?.client().map_err(NetConfError::Client)
+}
+
+/// Starts a GET request with any authentication configured for the URL's host
+pub fn get(client: &Client, url: &str) -> RequestBuilder {
+    let mut request = client.get(url);
+
+    // Clients can't be created from an invalid configuration, so there's no
+    // authentication to add if it failed to load
+    if let Some(auth) = NetConfig::get().ok().and_then(|c| c.auth_for(url)) {
+        for (name, value) in auth.header_values() {
+            match HeaderValue::try_from(value) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    request = request.header(name, value);
+                }
+                Err(e) => warn!("Skipping invalid header \"{}\": {}", name, e),
+            }
+        }
+    }
+
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = NetConfig::default();
+
+        assert_eq!(config.backoff(1), Duration::from_secs(1));
+        assert_eq!(config.backoff(3), Duration::from_secs(4));
+        assert_eq!(config.backoff(20), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_auth_for() {
+        let file: NetConfigFile = serde_jsonc::from_str(
+            r#"{
+                "hosts": {
+                    "repo.example.com": { "bearer": "abc" },
+                    "repo.example.com:8443": {
+                        "basic": { "username": "user", "password": "pass" },
+                        "headers": { "X-Api-Key": "key" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let config = NetConfig {
+            hosts: file.hosts,
+            ..Default::default()
+        };
+
+        let bearer = config
+            .auth_for("https://repo.example.com/a.jar")
+            .unwrap()
+            .header_values();
+        assert_eq!(
+            bearer,
+            vec![("authorization".to_string(), "Bearer abc".to_string())]
+        );
+
+        let mut basic = config
+            .auth_for("https://repo.example.com:8443/a.jar")
+            .unwrap()
+            .header_values();
+        basic.sort();
+        assert_eq!(
+            basic,
+            vec![
+                ("X-Api-Key".to_string(), "key".to_string()),
+                (
+                    "authorization".to_string(),
+                    "Basic dXNlcjpwYXNz".to_string()
+                ),
+            ]
+        );
+
+        assert!(config.auth_for("https://other.example.com/a.jar").is_none());
+        assert_eq!(
+            token_env_var("repo.example.com"),
+            "VKCONSTRUCT_TOKEN_REPO_EXAMPLE_COM"
+        );
+    }
+
+    #[test]
+    fn test_git_env() {
+        let config = NetConfig {
+            hosts: HashMap::from([(
+                "repo.example.com".to_string(),
+                HostAuth {
+                    bearer: Some("abc".to_string()),
+                    headers: HashMap::from([("X-Api-Key".to_string(), "key".to_string())]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let env = config.git_env("https://repo.example.com/repo.git");
+
+        assert!(env.contains(&(
+            "GIT_CONFIG_KEY_0".to_string(),
+            "http.https://repo.example.com/.extraHeader".to_string()
+        )));
+        assert!(env.contains(&("GIT_CONFIG_VALUE_2".to_string(), "false".to_string())));
+        assert!(!env.iter().any(|(_, v)| v == "http.extraHeader"));
+    }
+
+    #[tokio::test]
+    async fn test_cross_host_redirect() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    port
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let config = NetConfig {
+            hosts: HashMap::from([(
+                format!("127.0.0.1:{}", port),
+                HostAuth {
+                    headers: HashMap::from([("X-Api-Key".to_string(), "key".to_string())]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let error = config
+            .client()
+            .unwrap()
+            .get(format!("http://127.0.0.1:{}/", port))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(error.is_redirect());
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::build::BuildInfo;
+use crate::vkstore::VolkanicStore;
+
+/// Environment variable with the RCON password
+pub const PASSWORD_ENV: &str = "VKCONSTRUCT_RCON_PASSWORD";
+/// Template variables used when `server.properties` doesn't set the port or
+/// password
+pub const PORT_VAR: &str = "RCON_PORT";
+pub const PASSWORD_VAR: &str = "RCON_PASSWORD";
+
+const PROPERTIES_FILE: &str = "server.properties";
+const DEFAULT_PORT: u16 = 25575;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest command the Minecraft server accepts
+const MAX_COMMAND_LEN: usize = 1446;
+/// Largest packet accepted from the server. Minecraft splits responses every
+/// 4096 UTF-16 characters, each up to 3 bytes in UTF-8
+const MAX_PACKET_LEN: usize = 4096 * 3 + 10;
+
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_COMMAND: i32 = 2;
+const TYPE_AUTH: i32 = 3;
+/// Request ID the server answers with when authentication fails
+const AUTH_FAILED_ID: i32 = -1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RconError {
+    #[error("Connection error: {0}")]
+    Io(std::io::Error),
+    #[error("Timed out waiting for the server")]
+    Timeout,
+    #[error("Authentication failed, check the RCON password")]
+    AuthFailed,
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+    #[error("Command is longer than {MAX_COMMAND_LEN} bytes")]
+    CommandTooLong,
+    #[error("No RCON password found in server.properties, variables or options")]
+    NoPassword,
+    #[error("Invalid RCON port: {0}")]
+    InvalidPort(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let len = 4 + 4 + self.body.len() + 2;
+        let mut buf = Vec::with_capacity(4 + len);
+
+        buf.extend((len as i32).to_le_bytes());
+        buf.extend(self.id.to_le_bytes());
+        buf.extend(self.kind.to_le_bytes());
+        buf.extend(self.body.as_bytes());
+        buf.extend([0, 0]);
+
+        buf
+    }
+    async fn read<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<Self, RconError> {
+        let len = reader.read_i32_le().await.map_err(RconError::Io)?;
+
+        if !(10..=MAX_PACKET_LEN as i32).contains(&len) {
+            return Err(RconError::InvalidPacket(format!("length {}", len)));
+        }
+
+        let mut buf = vec![0; len as usize];
+        reader.read_exact(&mut buf).await.map_err(RconError::Io)?;
+
+        let id = i32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let kind = i32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let body = &buf[8..buf.len() - 2];
+
+        Ok(Self {
+            id,
+            kind,
+            body: String::from_utf8_lossy(body).to_string(),
+        })
+    }
+}
+
+/// Where to connect and how to authenticate
+#[derive(Debug, Clone)]
+pub struct RconConfig {
+    pub host: String,
+    pub port: u16,
+    pub password: String,
+}
+
+/// Reads the keys of a `.properties` file, ignoring comments and line
+/// continuations
+fn parse_properties(contents: &str) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+
+    for line in contents.lines().map(str::trim_start) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        let mut key = String::new();
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => key.extend(chars.next()),
+                '=' | ':' => break,
+                c if c.is_whitespace() => break,
+                c => key.push(c),
+            }
+        }
+
+        let value = chars
+            .as_str()
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '=' || c == ':');
+        let value = value
+            .replace("\\:", ":")
+            .replace("\\=", "=")
+            .replace("\\\\", "\\");
+
+        properties.insert(key, value);
+    }
+
+    properties
+}
+
+impl RconConfig {
+    /// Reads the RCON port and password from the build's `server.properties`,
+    /// falling back to the resolved template variables
+    pub async fn from_build(
+        store: &VolkanicStore,
+        host: String,
+        port: Option<u16>,
+        password: Option<String>,
+    ) -> Result<Self, RconError> {
+        let properties =
+            match tokio::fs::read_to_string(store.build_path.join(PROPERTIES_FILE)).await {
+                Ok(c) => parse_properties(&c),
+                Err(e) => {
+                    debug!("Unable to read {}: {}", PROPERTIES_FILE, e);
+                    HashMap::new()
+                }
+            };
+        let variables = match BuildInfo::get(store).await {
+            Ok(info) => info.variables,
+            Err(e) => {
+                debug!("Unable to read build info: {}", e);
+                HashMap::new()
+            }
+        };
+
+        if properties.get("enable-rcon").map(String::as_str) == Some("false") {
+            warn!("RCON is disabled in {}", PROPERTIES_FILE);
+        }
+
+        let lookup = |property: &str, var: &str| {
+            properties
+                .get(property)
+                .filter(|v| !v.is_empty())
+                .or_else(|| variables.get(var))
+                .cloned()
+        };
+
+        let port = match port {
+            Some(p) => p,
+            None => match lookup("rcon.port", PORT_VAR) {
+                Some(p) => p.trim().parse().map_err(|_| RconError::InvalidPort(p))?,
+                None => DEFAULT_PORT,
+            },
+        };
+        let password = password
+            .or_else(|| lookup("rcon.password", PASSWORD_VAR))
+            .filter(|p| !p.is_empty())
+            .ok_or(RconError::NoPassword)?;
+
+        Ok(Self {
+            host,
+            port,
+            password,
+        })
+    }
+}
+
+/// Source RCON client
+#[derive(Debug)]
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    /// Connects and authenticates
+    pub async fn connect(config: &RconConfig) -> Result<Self, RconError> {
+        let stream = tokio::time::timeout(
+            TIMEOUT,
+            TcpStream::connect((config.host.as_str(), config.port)),
+        )
+        .await
+        .map_err(|_| RconError::Timeout)?
+        .map_err(RconError::Io)?;
+
+        let mut rcon = Self { stream, next_id: 1 };
+        let id = rcon.send(TYPE_AUTH, &config.password).await?;
+
+        loop {
+            let packet = rcon.receive().await?;
+
+            if packet.id == AUTH_FAILED_ID {
+                return Err(RconError::AuthFailed);
+            }
+
+            // Some servers send an empty response before the auth response
+            if packet.id == id && packet.kind == TYPE_COMMAND {
+                break;
+            }
+        }
+
+        debug!("Authenticated with {}:{}", config.host, config.port);
+
+        Ok(rcon)
+    }
+    async fn send(&mut self, kind: i32, body: &str) -> Result<i32, RconError> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        let packet = Packet {
+            id,
+            kind,
+            body: body.to_string(),
+        };
+
+        self.stream
+            .write_all(&packet.encode())
+            .await
+            .map_err(RconError::Io)?;
+
+        Ok(id)
+    }
+    async fn receive(&mut self) -> Result<Packet, RconError> {
+        tokio::time::timeout(TIMEOUT, Packet::read(&mut self.stream))
+            .await
+            .map_err(|_| RconError::Timeout)?
+    }
+    /// Runs a command and returns the full response
+    pub async fn command(&mut self, command: &str) -> Result<String, RconError> {
+        if command.len() > MAX_COMMAND_LEN {
+            return Err(RconError::CommandTooLong);
+        }
+
+        let id = self.send(TYPE_COMMAND, command).await?;
+        // Long responses are split over several packets. The server answers
+        // requests in order, so the reply to this marker ends the response.
+        let marker = self.send(TYPE_RESPONSE, "").await?;
+        let mut response = String::new();
+
+        loop {
+            let packet = self.receive().await?;
+
+            if packet.id == marker {
+                break;
+            } else if packet.id == id {
+                response.push_str(&packet.body);
+            } else {
+                debug!("Ignoring RCON packet with ID {}", packet.id);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// Removes Minecraft formatting codes (e.g. `§a`) from a response
+pub fn strip_formatting(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Prints a response, leaving out the trailing newline some commands include
+pub fn print_response(response: &str, raw: bool) {
+    let response = if raw {
+        response.to_string()
+    } else {
+        strip_formatting(response)
+    };
+    let response = response.trim_end();
+
+    if !response.is_empty() {
+        println!("{}", response);
+    }
+}
+
+/// Runs commands read from stdin until it closes
+pub async fn repl(rcon: &mut Rcon, raw: bool) -> Result<(), RconError> {
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        if interactive {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+        }
+
+        let line = match lines.next_line().await.map_err(RconError::Io)? {
+            Some(line) => line,
+            None => break,
+        };
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        match rcon.command(line).await {
+            Ok(response) => print_response(&response, raw),
+            Err(RconError::CommandTooLong) => warn!("{}", RconError::CommandTooLong),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Minimal server which echoes commands, splitting long responses like
+    /// Minecraft does
+    async fn stub_server(password: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            while let Ok(packet) = Packet::read(&mut stream).await {
+                let replies = match packet.kind {
+                    TYPE_AUTH if packet.body == password => vec![(packet.id, String::new())],
+                    TYPE_AUTH => vec![(AUTH_FAILED_ID, String::new())],
+                    TYPE_COMMAND => {
+                        let body = match packet.body.as_str() {
+                            "wide" => "€".repeat(5000),
+                            command => format!("§aran {}", command),
+                        };
+                        let body = body.encode_utf16().collect::<Vec<_>>();
+
+                        body.chunks(4096)
+                            .map(|c| (packet.id, String::from_utf16_lossy(c)))
+                            .collect()
+                    }
+                    _ => vec![(packet.id, format!("Unknown request {}", packet.kind))],
+                };
+
+                for (id, body) in replies {
+                    let kind = if packet.kind == TYPE_AUTH {
+                        TYPE_COMMAND
+                    } else {
+                        TYPE_RESPONSE
+                    };
+                    let reply = Packet { id, kind, body };
+
+                    stream.write_all(&reply.encode()).await.unwrap();
+                }
+            }
+        });
+
+        port
+    }
+
+    fn config(port: u16, password: &str) -> RconConfig {
+        RconConfig {
+            host: "127.0.0.1".into(),
+            port,
+            password: password.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rcon() {
+        let port = stub_server("secret").await;
+        let mut rcon = Rcon::connect(&config(port, "secret")).await.unwrap();
+
+        assert_eq!(rcon.command("list").await.unwrap(), "§aran list");
+
+        let long = "x".repeat(MAX_COMMAND_LEN - 1);
+        let response = rcon.command(&long).await.unwrap();
+
+        assert_eq!(strip_formatting(&response), format!("ran {}", long));
+
+        // Full-size packets of multibyte characters
+        assert_eq!(rcon.command("wide").await.unwrap(), "€".repeat(5000));
+        assert!(matches!(
+            rcon.command(&"x".repeat(MAX_COMMAND_LEN + 1)).await,
+            Err(RconError::CommandTooLong)
+        ));
+
+        let port = stub_server("secret").await;
+
+        assert!(matches!(
+            Rcon::connect(&config(port, "wrong")).await,
+            Err(RconError::AuthFailed)
+        ));
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let properties = parse_properties(
+            "# comment\nrcon.port=25575\nrcon.password = pa\\=ss\nmotd=A: server\nempty=\n",
+        );
+
+        assert_eq!(properties["rcon.port"], "25575");
+        assert_eq!(properties["rcon.password"], "pa=ss");
+        assert_eq!(properties["motd"], "A: server");
+        assert_eq!(properties["empty"], "");
+    }
+}
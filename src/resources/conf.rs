@@ -7,3 +7,8 @@ pub const JDK_BIN_FILE: &str = "bin/java.exe";
 
 #[cfg(target_os = "windows")]
 pub const WIN_SHELL_CMD: &str = "cmd.exe";
+
+/// Largest total size of an extracted archive
+pub const MAX_EXTRACTED_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+/// Largest number of entries in an extracted archive
+pub const MAX_ARCHIVE_ENTRIES: usize = 200_000;
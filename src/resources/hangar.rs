@@ -0,0 +1,190 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::netconf;
+
+use super::{api_url, get_json, Error, RemoteFile};
+
+const HANGAR_API_URL: &str = "https://hangar.papermc.io/api/v1";
+
+/// Platform a Hangar version is published for
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum HangarPlatform {
+    #[default]
+    #[serde(rename = "paper")]
+    Paper,
+    #[serde(rename = "waterfall")]
+    Waterfall,
+    #[serde(rename = "velocity")]
+    Velocity,
+}
+
+impl HangarPlatform {
+    fn api_name(&self) -> &'static str {
+        match self {
+            HangarPlatform::Paper => "PAPER",
+            HangarPlatform::Waterfall => "WATERFALL",
+            HangarPlatform::Velocity => "VELOCITY",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FileInfo {
+    name: String,
+    #[serde(rename = "sha256Hash")]
+    sha256_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Download {
+    #[serde(rename = "fileInfo")]
+    file_info: Option<FileInfo>,
+    #[serde(rename = "externalUrl")]
+    external_url: Option<String>,
+    #[serde(rename = "downloadUrl")]
+    download_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Version {
+    name: String,
+    downloads: HashMap<String, Download>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionList {
+    result: Vec<Version>,
+}
+
+/// Strips the optional author from an "author/slug" project name, as Hangar
+/// slugs are unique on their own
+fn project_slug(project: &str) -> &str {
+    project.rsplit('/').next().unwrap_or(project)
+}
+
+/// Resolves the download of a Hangar project version
+///
+/// If no version is given, the newest version in the channel ("Release" by
+/// default) compatible with the platform version is used.
+pub async fn resolve(
+    project: &str,
+    version: Option<&str>,
+    platform: &HangarPlatform,
+    platform_version: Option<&str>,
+    channel: Option<&str>,
+) -> Result<RemoteFile, Error> {
+    let client = netconf::client().map_err(Error::NetConfig)?;
+    let slug = project_slug(project);
+
+    let version = match version {
+        Some(v) => {
+            let url = api_url(HANGAR_API_URL, &["projects", slug, "versions", v], &[])?;
+
+            get_json::<Version>(&client, url.as_str()).await?
+        }
+        None => {
+            let mut query = vec![
+                ("limit", "1"),
+                ("offset", "0"),
+                ("platform", platform.api_name()),
+                ("channel", channel.unwrap_or("Release")),
+            ];
+
+            if let Some(platform_version) = platform_version {
+                query.push(("platformVersion", platform_version));
+            }
+
+            let url = api_url(HANGAR_API_URL, &["projects", slug, "versions"], &query)?;
+
+            get_json::<VersionList>(&client, url.as_str())
+                .await?
+                .result
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::PluginVersionNotFound(project.to_string()))?
+        }
+    };
+
+    info!(
+        "Using version \"{}\" of Hangar project \"{}\"",
+        version.name, slug
+    );
+
+    let download = version
+        .downloads
+        .into_iter()
+        .find(|(p, _)| p == platform.api_name())
+        .map(|(_, d)| d)
+        .ok_or_else(|| Error::PluginVersionNotFound(format!("{} {}", project, version.name)))?;
+
+    match (
+        download.download_url,
+        download.external_url,
+        download.file_info,
+    ) {
+        (Some(url), _, Some(file_info)) => Ok(RemoteFile {
+            url,
+            file_name: file_info.name,
+            sha256: file_info.sha256_hash,
+        }),
+        (Some(url), _, None) => Ok(RemoteFile {
+            url,
+            file_name: format!("{}-{}.jar", slug, version.name),
+            sha256: None,
+        }),
+        (None, Some(url), _) => {
+            warn!("\"{}\" is hosted externally and can't be verified", project);
+
+            Ok(RemoteFile {
+                url,
+                file_name: format!("{}-{}.jar", slug, version.name),
+                sha256: None,
+            })
+        }
+        (None, None, _) => Err(Error::PluginVersionNotFound(format!(
+            "{} {}",
+            project, version.name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version: Version = serde_jsonc::from_str(
+            r#"{
+                "name": "5.2.1",
+                "downloads": {
+                    "PAPER": {
+                        "fileInfo": {
+                            "name": "ViaVersion-5.2.1.jar",
+                            "sizeBytes": 5000000,
+                            "sha256Hash": "abc123"
+                        },
+                        "externalUrl": null,
+                        "downloadUrl": "https://hangarcdn.papermc.io/plugins/ViaVersion/ViaVersion/versions/5.2.1/PAPER/ViaVersion-5.2.1.jar"
+                    }
+                },
+                "platformDependencies": { "PAPER": ["1.21.4"] }
+            }"#,
+        )
+        .unwrap();
+
+        let download = &version.downloads["PAPER"];
+
+        assert_eq!(version.name, "5.2.1");
+        assert_eq!(
+            download.file_info.as_ref().unwrap().sha256_hash.as_deref(),
+            Some("abc123")
+        );
+        assert!(download.download_url.is_some());
+        assert_eq!(project_slug("ViaVersion/ViaVersion"), "ViaVersion");
+        assert_eq!(project_slug("ViaVersion"), "ViaVersion");
+    }
+}
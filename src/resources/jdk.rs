@@ -141,10 +141,9 @@ impl JdkLookup {
             // Construct the JDK object with information found from Disco
             return Ok(Some(Jdk {
                 file_name: Some(p.filename),
-                format: match p.archive_type.as_str() {
-                    "tar.gz" => ArchiveFormat::TarGz,
-                    "zip" => ArchiveFormat::Zip,
-                    _ => continue,
+                format: match ArchiveFormat::from_extension(&p.archive_type) {
+                    Some(format) => format,
+                    None => continue,
                 },
                 home_path: HomePathType::Auto,
                 sha256: verification,
@@ -1,10 +1,15 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::netconf;
+
 pub mod conf;
+pub mod hangar;
 mod jdk;
+pub mod spiget;
 pub mod style;
 
+pub use hangar::HangarPlatform;
 pub use jdk::{HomePathType, Jdk, JdkLookup};
 
 #[derive(Debug, thiserror::Error)]
@@ -15,12 +20,172 @@ pub enum Error {
     UnknownArchitecture,
     #[error("Failed to fetch operating system")]
     UnknownOperatingSystem,
+    #[error("HTTP error: {0}")]
+    Http(reqwest::Error),
+    #[error("Failed to parse JSON: {0}")]
+    Json(serde_jsonc::Error),
+    #[error("No matching plugin version found: {0}")]
+    PluginVersionNotFound(String),
+    #[error("SpigotMC resource {0} is premium and can't be downloaded")]
+    PremiumResource(u64),
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Network configuration error: {0}")]
+    NetConfig(netconf::NetConfError),
+}
+
+/// Downloadable file resolved from a plugin repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteFile {
+    pub url: String,
+    pub file_name: String,
+    pub sha256: Option<String>,
+}
+
+/// Builds an API URL, encoding each path segment and query value
+pub(crate) fn api_url(
+    base: &str,
+    segments: &[&str],
+    query: &[(&str, &str)],
+) -> Result<reqwest::Url, Error> {
+    let mut url = reqwest::Url::parse(base).map_err(|e| Error::InvalidUrl(e.to_string()))?;
+
+    url.path_segments_mut()
+        .map_err(|_| Error::InvalidUrl(base.to_string()))?
+        .pop_if_empty()
+        .extend(segments);
+
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+
+    Ok(url)
+}
+
+/// Fetches and deserializes JSON from a plugin repository API
+pub(crate) async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<T, Error> {
+    let text = netconf::get(client, url)
+        .header(
+            reqwest::header::USER_AGENT,
+            format!("8Bitz0/volkanicmc/{}", env!("CARGO_PKG_VERSION")),
+        )
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(Error::Http)?
+        .text()
+        .await
+        .map_err(Error::Http)?;
+
+    serde_jsonc::from_str(&text).map_err(Error::Json)
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 pub enum ArchiveFormat {
     #[serde(rename = "tar.gz")]
     TarGz,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "tar.bz2")]
+    TarBz2,
+    #[serde(rename = "tar")]
+    Tar,
     #[serde(rename = "zip")]
     Zip,
+    /// Java archive, extracted as a zip
+    #[serde(rename = "jar")]
+    Jar,
+}
+
+/// Offset of the magic string in a tar header
+const TAR_MAGIC_OFFSET: usize = 257;
+
+impl ArchiveFormat {
+    /// Number of bytes needed to detect a format
+    pub const MAGIC_LEN: usize = TAR_MAGIC_OFFSET + 5;
+
+    /// Returns the format for a file extension or archive type (e.g. "tgz")
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.trim_start_matches('.') {
+            "tar.gz" | "tgz" => Some(Self::TarGz),
+            "tar.xz" | "txz" => Some(Self::TarXz),
+            "tar.zst" | "tzst" => Some(Self::TarZst),
+            "tar.bz2" | "tbz2" => Some(Self::TarBz2),
+            "tar" => Some(Self::Tar),
+            "zip" => Some(Self::Zip),
+            "jar" => Some(Self::Jar),
+            _ => None,
+        }
+    }
+    /// Detects the format from the first bytes of a file
+    ///
+    /// Compressed files are assumed to contain a tar archive, and JARs are
+    /// detected as zip archives.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::TarXz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if header.starts_with(b"BZh") {
+            Some(Self::TarBz2)
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if header.get(TAR_MAGIC_OFFSET..Self::MAGIC_LEN) == Some(b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_archive_format() {
+        assert_eq!(
+            ArchiveFormat::detect(&[0x1f, 0x8b, 0x08]),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(b"\xfd7zXZ\x00\x00"),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::detect(b"PK\x03\x04\x14\x00"),
+            Some(ArchiveFormat::Zip)
+        );
+
+        let mut tar = vec![0; ArchiveFormat::MAGIC_LEN];
+        tar[TAR_MAGIC_OFFSET..].copy_from_slice(b"ustar");
+        assert_eq!(ArchiveFormat::detect(&tar), Some(ArchiveFormat::Tar));
+
+        assert_eq!(ArchiveFormat::detect(b"plain text"), None);
+        assert_eq!(
+            ArchiveFormat::from_extension("tgz"),
+            Some(ArchiveFormat::TarGz)
+        );
+    }
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!(
+            api_url(
+                "https://hangar.papermc.io/api/v1",
+                &["projects", "a b", "versions", "1.0#x"],
+                &[("channel", "Beta & RC"), ("platformVersion", "1.21")],
+            )
+            .unwrap()
+            .as_str(),
+            "https://hangar.papermc.io/api/v1/projects/a%20b/versions/1.0%23x?channel=Beta+%26+RC&platformVersion=1.21"
+        );
+    }
 }
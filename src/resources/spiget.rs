@@ -0,0 +1,88 @@
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::netconf;
+
+use super::{api_url, get_json, Error, RemoteFile};
+
+const SPIGET_API_URL: &str = "https://api.spiget.org/v2";
+
+#[derive(Debug, Deserialize)]
+struct ResourceFile {
+    #[serde(rename = "type")]
+    file_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resource {
+    #[serde(default)]
+    premium: bool,
+    #[serde(default)]
+    external: bool,
+    file: Option<ResourceFile>,
+}
+
+/// Whether a file type from Spiget is a plain extension (e.g. ".jar"), as
+/// it's used in the file name
+fn is_plain_extension(file_type: &str) -> bool {
+    file_type
+        .strip_prefix('.')
+        .is_some_and(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Resolves the download of a SpigotMC resource through Spiget
+///
+/// Spiget doesn't provide checksums and SpigotMC may block some downloads, so
+/// this is best-effort only.
+pub async fn resolve(resource: u64, version: Option<u64>) -> Result<RemoteFile, Error> {
+    let client = netconf::client().map_err(Error::NetConfig)?;
+
+    let resource_id = resource.to_string();
+    let url = api_url(SPIGET_API_URL, &["resources", &resource_id], &[])?;
+    let info: Resource = get_json(&client, url.as_str()).await?;
+
+    if info.premium {
+        return Err(Error::PremiumResource(resource));
+    }
+
+    if info.external {
+        warn!(
+            "SpigotMC resource {} is hosted externally, the download may fail",
+            resource
+        );
+    }
+
+    let extension = match info.file.and_then(|f| f.file_type) {
+        Some(t) if is_plain_extension(&t) => t,
+        _ => ".jar".to_string(),
+    };
+
+    Ok(match version {
+        Some(version) => {
+            let version_id = version.to_string();
+            let segments = [
+                "resources",
+                &resource_id,
+                "versions",
+                &version_id,
+                "download",
+            ];
+
+            RemoteFile {
+                url: api_url(SPIGET_API_URL, &segments, &[])?.to_string(),
+                file_name: format!("spiget-{resource}-{version}{extension}"),
+                sha256: None,
+            }
+        }
+        None => RemoteFile {
+            url: api_url(
+                SPIGET_API_URL,
+                &["resources", &resource_id, "download"],
+                &[],
+            )?
+            .to_string(),
+            file_name: format!("spiget-{resource}{extension}"),
+            sha256: None,
+        },
+    })
+}
@@ -61,7 +61,7 @@ pub async fn import<P: AsRef<Path>>(build_path: P, archive_path: P) -> Result<()
             },
         };
 
-        let to_path = build_path.as_ref().to_path_buf().join(obj.1);
+        let to_path = fsobj::safe_join(build_path.as_ref(), obj.1).map_err(Error::UnsafePath)?;
         fsobj::create_ancestors(&to_path)
             .await
             .map_err(Error::CreateAncestor)?;
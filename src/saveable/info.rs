@@ -8,6 +8,7 @@ use tracing::{debug, error};
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+use crate::fsobj;
 use crate::vkstore::VolkanicStore;
 
 use super::{export::export, Error};
@@ -28,7 +29,8 @@ impl ExportInfo {
         }
     }
     pub async fn add<P: AsRef<Path>>(&mut self, inner_path: P) -> Result<(), Error> {
-        let path = self.build_path.join(inner_path.as_ref());
+        let path =
+            fsobj::safe_join(&self.build_path, inner_path.as_ref()).map_err(Error::UnsafePath)?;
 
         if path.is_file() {
             self.objects.insert(
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use crate::fsobj::CreateAncestorError;
+use crate::fsobj::{CreateAncestorError, UnsafePathError};
 
 mod export;
 mod import;
@@ -35,4 +35,6 @@ pub enum Error {
     NotInArchive(PathBuf),
     #[error("Create path ancestors error: {0}")]
     CreateAncestor(CreateAncestorError),
+    #[error("Unsafe path: {0}")]
+    UnsafePath(UnsafePathError),
 }
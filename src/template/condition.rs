@@ -0,0 +1,112 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::var::VarMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConditionError {
+    #[error("Condition references undefined variable: {0}")]
+    UndefinedVariable(String),
+}
+
+/// Condition evaluated against template variables (including built-ins such
+/// as `host.os` and `host.arch`)
+///
+/// Example: `{ "equals": { "var": "ENABLE_BEDROCK", "value": "true" } }`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Condition {
+    /// The variable equals the value
+    #[serde(rename = "equals")]
+    Equals { var: String, value: String },
+    /// The variable does not equal the value
+    #[serde(rename = "not-equals")]
+    NotEquals { var: String, value: String },
+    /// The variable equals any of the values
+    #[serde(rename = "one-of")]
+    OneOf { var: String, values: Vec<String> },
+    /// All inner conditions are true
+    #[serde(rename = "all")]
+    All(Vec<Condition>),
+    /// At least one inner condition is true
+    #[serde(rename = "any")]
+    Any(Vec<Condition>),
+    /// The inner condition is false
+    #[serde(rename = "not")]
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn evaluate(&self, vars: &VarMap) -> Result<bool, ConditionError> {
+        let get = |var: &String| {
+            vars.get(var)
+                .ok_or_else(|| ConditionError::UndefinedVariable(var.clone()))
+        };
+
+        Ok(match self {
+            Condition::Equals { var, value } => get(var)? == value,
+            Condition::NotEquals { var, value } => get(var)? != value,
+            Condition::OneOf { var, values } => values.contains(get(var)?),
+            Condition::All(conditions) => {
+                for c in conditions {
+                    if !c.evaluate(vars)? {
+                        return Ok(false);
+                    }
+                }
+
+                true
+            }
+            Condition::Any(conditions) => {
+                for c in conditions {
+                    if c.evaluate(vars)? {
+                        return Ok(true);
+                    }
+                }
+
+                false
+            }
+            Condition::Not(condition) => !condition.evaluate(vars)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let vars = VarMap::from([
+            ("ENABLE_BEDROCK".to_string(), "true".to_string()),
+            ("host.arch".to_string(), "arm64".to_string()),
+        ]);
+
+        let condition: Condition = serde_jsonc::from_str(
+            r#"{
+                "all": [
+                    { "equals": { "var": "ENABLE_BEDROCK", "value": "true" } },
+                    { "not": { "one-of": { "var": "host.arch", "values": ["x86", "amd64"] } } }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(condition.evaluate(&vars).unwrap());
+
+        let condition = Condition::Any(vec![Condition::NotEquals {
+            var: "host.arch".into(),
+            value: "arm64".into(),
+        }]);
+
+        assert!(!condition.evaluate(&vars).unwrap());
+
+        let condition = Condition::Equals {
+            var: "MISSING".into(),
+            value: "".into(),
+        };
+
+        assert!(matches!(
+            condition.evaluate(&vars),
+            Err(ConditionError::UndefinedVariable(_))
+        ));
+    }
+}
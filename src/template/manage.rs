@@ -1,7 +1,8 @@
 use base64::Engine;
+use std::path::Path;
 
 use tokio::{fs, io::AsyncReadExt};
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::resources;
 
@@ -13,18 +14,53 @@ pub enum TemplateManagementError {
     Filesystem(std::io::Error),
 }
 
-pub async fn embed(template: Template) -> Result<Template, TemplateManagementError> {
+/// Reads a file and encodes its contents with Base64
+async fn read_base64<P: AsRef<Path>>(path: P) -> Result<String, TemplateManagementError> {
+    let mut f = fs::File::open(path)
+        .await
+        .map_err(TemplateManagementError::Filesystem)?;
+    let mut buffer = [0; resources::conf::FILE_BUFFER_SIZE];
+
+    let mut f_contents: Vec<u8> = vec![];
+
+    loop {
+        let bytes_read = f
+            .read(&mut buffer)
+            .await
+            .map_err(TemplateManagementError::Filesystem)?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        f_contents.append(&mut buffer[..bytes_read].to_vec());
+    }
+
+    let base64_config = base64::engine::GeneralPurposeConfig::new();
+    let base64_engine =
+        base64::engine::GeneralPurpose::new(&base64::alphabet::STANDARD, base64_config);
+
+    Ok(base64_engine.encode(&f_contents))
+}
+
+/// Embeds include files and local files into the template as Base64
+///
+/// Local directories are left as-is.
+pub async fn embed(
+    template: Template,
+    include_dir: Option<&Path>,
+) -> Result<Template, TemplateManagementError> {
     let mut new_template = template;
 
+    let mut include = vkinclude::VolkanicInclude::new(&new_template.source_dir).await;
+    if let Some(include_dir) = include_dir {
+        include = include.override_path(include_dir).await;
+    }
+
     for r in &mut new_template.resources {
-        match r {
-            resource::GenericResource::Include {
-                include_id,
-                use_variables,
-                template_path,
-            } => {
-                let include = vkinclude::VolkanicInclude::new().await;
-                let p = match include.get(&include_id).await {
+        let p = match r {
+            resource::GenericResource::Include { include_id, .. } => {
+                match include.get(&include_id).await {
                     Some(p) => p,
                     None => {
                         error!(
@@ -33,36 +69,42 @@ pub async fn embed(template: Template) -> Result<Template, TemplateManagementErr
                         );
                         continue;
                     }
-                };
-
-                let mut f = fs::File::open(p)
-                    .await
-                    .map_err(TemplateManagementError::Filesystem)?;
-                let mut buffer = [0; resources::conf::FILE_BUFFER_SIZE];
-
-                let mut f_contents: Vec<u8> = vec![];
-
-                loop {
-                    let bytes_read = f
-                        .read(&mut buffer)
-                        .await
-                        .map_err(TemplateManagementError::Filesystem)?;
-
-                    if bytes_read == 0 {
-                        break;
-                    }
+                }
+            }
+            resource::GenericResource::Local { path, .. } => {
+                let p = new_template.source_dir.join(path);
 
-                    f_contents.append(&mut buffer[..bytes_read].to_vec());
+                if !p.is_file() {
+                    warn!(
+                        "\"{}\" is not a file, skipping resource...",
+                        p.to_string_lossy()
+                    );
+                    continue;
                 }
 
-                let base64_config = base64::engine::GeneralPurposeConfig::new();
-                let base64_engine =
-                    base64::engine::GeneralPurpose::new(&base64::alphabet::STANDARD, base64_config);
+                p
+            }
+            _ => continue,
+        };
 
+        match r {
+            resource::GenericResource::Include {
+                use_variables,
+                template_path,
+                when,
+                ..
+            }
+            | resource::GenericResource::Local {
+                use_variables,
+                template_path,
+                when,
+                ..
+            } => {
                 *r = resource::GenericResource::Base64 {
-                    base64: base64_engine.encode(&f_contents),
+                    base64: read_base64(p).await?,
                     use_variables: use_variables.clone(),
                     template_path: template_path.clone(),
+                    when: when.clone(),
                 };
             }
             _ => continue,
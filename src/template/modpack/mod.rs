@@ -0,0 +1,412 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::build;
+use crate::netconf;
+use crate::resources;
+
+use super::resource::{GenericResource, ServerRuntimeResource};
+use super::{Template, TEMPLATE_FORMAT};
+
+pub mod mrpack;
+
+const MOJANG_MANIFEST_URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+const FABRIC_META_URL: &str = "https://meta.fabricmc.net/v2";
+
+/// Name of the template file written to the output directory
+pub const TEMPLATE_FILE_NAME: &str = "template.json";
+/// Name of the directory overrides are extracted to
+pub const OVERRIDES_DIR_NAME: &str = "overrides";
+
+const SERVER_JAR: &str = "server.jar";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ModpackError {
+    #[error("Filesystem error: {0}")]
+    Filesystem(std::io::Error),
+    #[error("Zip archive error: {0}")]
+    Zip(zip::result::ZipError),
+    #[error("Archive error: {0}")]
+    Archive(build::archive::ArchiveError),
+    #[error("Failed to parse JSON: {0}")]
+    Json(serde_jsonc::Error),
+    #[error("Lookup error: {0}")]
+    Lookup(resources::Error),
+    #[error("Not a valid modpack: {0}")]
+    Invalid(String),
+    #[error("Mod loader isn't supported yet, only Fabric and vanilla packs can be imported: {0}")]
+    UnsupportedLoader(String),
+    #[error("Unknown Minecraft version: {0}")]
+    UnknownMinecraftVersion(String),
+    #[error("Unsafe path in modpack: {0}")]
+    UnsafePath(String),
+    #[error("Output already exists: {0}")]
+    OutputExists(PathBuf),
+}
+
+/// Mod loader providing the server runtime
+#[derive(Clone, Debug, PartialEq)]
+pub enum Loader {
+    Vanilla,
+    Fabric(String),
+}
+
+/// Modpack contents common to all pack formats
+#[derive(Clone, Debug)]
+struct Pack {
+    name: String,
+    description: String,
+    author: Option<String>,
+    mc_version: String,
+    loader: Loader,
+    /// Resources for the pack's files
+    files: Vec<GenericResource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangVersionEntry {
+    id: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangManifest {
+    versions: Vec<MojangVersionEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangDownload {
+    url: String,
+    sha1: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangDownloads {
+    server: Option<MojangDownload>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangJavaVersion {
+    #[serde(rename = "majorVersion")]
+    major_version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct MojangVersion {
+    downloads: MojangDownloads,
+    #[serde(rename = "javaVersion")]
+    java_version: Option<MojangJavaVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FabricInstaller {
+    version: String,
+    stable: bool,
+}
+
+/// Creates the runtime and server JAR resource for a Minecraft version and
+/// mod loader
+async fn runtime(
+    mc_version: &str,
+    loader: &Loader,
+) -> Result<(ServerRuntimeResource, GenericResource), ModpackError> {
+    let client =
+        netconf::client().map_err(|e| ModpackError::Lookup(resources::Error::NetConfig(e)))?;
+
+    let manifest: MojangManifest = resources::get_json(&client, MOJANG_MANIFEST_URL)
+        .await
+        .map_err(ModpackError::Lookup)?;
+
+    let entry = manifest
+        .versions
+        .into_iter()
+        .find(|v| v.id == mc_version)
+        .ok_or_else(|| ModpackError::UnknownMinecraftVersion(mc_version.to_string()))?;
+
+    let version: MojangVersion = resources::get_json(&client, &entry.url)
+        .await
+        .map_err(ModpackError::Lookup)?;
+
+    // Versions before Java versions were listed all run on Java 8
+    let java_version = version.java_version.map(|j| j.major_version).unwrap_or(8);
+
+    let (url, sha1) = match loader {
+        Loader::Vanilla => {
+            let server = version
+                .downloads
+                .server
+                .ok_or_else(|| ModpackError::UnknownMinecraftVersion(mc_version.to_string()))?;
+
+            (server.url, Some(server.sha1))
+        }
+        Loader::Fabric(loader_version) => {
+            let installers: Vec<FabricInstaller> =
+                resources::get_json(&client, &format!("{FABRIC_META_URL}/versions/installer"))
+                    .await
+                    .map_err(ModpackError::Lookup)?;
+
+            let installer = installers
+                .into_iter()
+                .find(|i| i.stable)
+                .ok_or_else(|| ModpackError::UnsupportedLoader("fabric".into()))?;
+
+            (
+                format!(
+                    "{FABRIC_META_URL}/versions/loader/{mc_version}/{loader_version}/{}/server/jar",
+                    installer.version
+                ),
+                None,
+            )
+        }
+    };
+
+    info!(
+        "Using Java {} for Minecraft {} ({:?})",
+        java_version, mc_version, loader
+    );
+
+    Ok((
+        ServerRuntimeResource::Jdk {
+            version: java_version.to_string(),
+            jar_path: PathBuf::from(SERVER_JAR),
+            jdk_args: vec![],
+            server_args: vec!["nogui".into()],
+            stop_command: None,
+        },
+        GenericResource::Remote {
+            url,
+            mirrors: vec![],
+            user_agent: None,
+            override_name: Some(SERVER_JAR.into()),
+            sha512: None,
+            sha256: None,
+            sha1,
+            use_variables: None,
+            archive: None,
+            template_path: PathBuf::from(SERVER_JAR),
+            when: None,
+        },
+    ))
+}
+
+/// Checks that a path from a modpack stays inside the build
+fn safe_path(path: &str) -> Result<PathBuf, ModpackError> {
+    let p = PathBuf::from(path);
+
+    if p.as_os_str().is_empty() || !p.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(ModpackError::UnsafePath(path.to_string()));
+    }
+
+    Ok(p)
+}
+
+/// Extracts all files under the given archive directories into `dest`, so
+/// later directories take precedence
+///
+/// Returns whether any files were extracted.
+fn extract_overrides<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    dirs: &[&str],
+    dest: &Path,
+    limits: build::archive::Limits,
+) -> Result<bool, ModpackError> {
+    let present = archive
+        .file_names()
+        .filter_map(|n| build::archive::relative_path(Path::new(n)))
+        .collect::<HashSet<_>>();
+    let mut extracted = false;
+
+    build::archive::unpack_zip_entries(archive, dest, limits, |path| {
+        let (i, rel) = dirs
+            .iter()
+            .enumerate()
+            .find_map(|(i, d)| Some((i, path.strip_prefix(d).ok()?)))?;
+
+        // Skip files a later directory overrides
+        if dirs[i + 1..]
+            .iter()
+            .any(|d| present.contains(&Path::new(d).join(rel)))
+        {
+            return None;
+        }
+
+        extracted |= !rel.as_os_str().is_empty();
+
+        Some(rel.to_path_buf())
+    })
+    .map_err(ModpackError::Archive)?;
+
+    Ok(extracted)
+}
+
+/// Reads a JSON file from an archive
+fn read_json<R: Read + Seek, T: serde::de::DeserializeOwned>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<T, ModpackError> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|_| ModpackError::Invalid(format!("missing \"{}\"", name)))?;
+
+    let mut json = String::new();
+    file.read_to_string(&mut json)
+        .map_err(ModpackError::Filesystem)?;
+
+    serde_jsonc::from_str(&json).map_err(ModpackError::Json)
+}
+
+/// Assembles the template for an imported modpack, extracts overrides and
+/// writes everything to the output directory
+async fn write_template<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    override_dirs: &[&str],
+    pack: Pack,
+    output: &Path,
+) -> Result<Template, ModpackError> {
+    let template_file = output.join(TEMPLATE_FILE_NAME);
+
+    if template_file.exists() {
+        return Err(ModpackError::OutputExists(template_file));
+    }
+
+    let (runtime, server_jar) = runtime(&pack.mc_version, &pack.loader).await?;
+
+    fs::create_dir_all(output)
+        .await
+        .map_err(ModpackError::Filesystem)?;
+
+    let mut resources = vec![server_jar];
+
+    if extract_overrides(
+        archive,
+        override_dirs,
+        &output.join(OVERRIDES_DIR_NAME),
+        build::archive::Limits::default(),
+    )? {
+        resources.push(GenericResource::Local {
+            path: PathBuf::from(OVERRIDES_DIR_NAME),
+            include: vec![],
+            exclude: vec![],
+            use_variables: None,
+            template_path: PathBuf::from("."),
+            when: None,
+        });
+    }
+
+    resources.extend(pack.files);
+
+    let template = Template {
+        template_format: TEMPLATE_FORMAT,
+        name: pack.name,
+        description: pack.description,
+        author: pack.author,
+        version: None,
+        variables: vec![],
+        runtime,
+        resources,
+        saveables: [
+            "world/",
+            "logs/",
+            "banned-ips.json",
+            "banned-players.json",
+            "ops.json",
+            "usercache.json",
+            "whitelist.json",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect(),
+        limits: None,
+        source_dir: output.to_path_buf(),
+    };
+
+    let json = serde_jsonc::to_string_pretty(&template).map_err(ModpackError::Json)?;
+
+    fs::write(&template_file, json)
+        .await
+        .map_err(ModpackError::Filesystem)?;
+
+    warn!("The Minecraft EULA must be accepted separately before running the server");
+
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_path() {
+        assert!(safe_path("mods/sodium.jar").is_ok());
+        assert!(safe_path("../mods/sodium.jar").is_err());
+        assert!(safe_path("/etc/passwd").is_err());
+        assert!(safe_path("mods/../../x").is_err());
+        assert!(safe_path("").is_err());
+    }
+
+    fn pack(entries: &[(&str, &[u8])]) -> zip::ZipArchive<std::io::Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+
+        for (name, data) in entries {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            std::io::Write::write_all(&mut writer, data).unwrap();
+        }
+
+        zip::ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_extract_overrides() {
+        let temp = tempfile::tempdir().unwrap();
+        let dest = temp.path().join("overrides");
+        let mut archive = pack(&[
+            ("modrinth.index.json", b"{}"),
+            ("server-overrides/config/a.toml", b"server"),
+            ("overrides/config/a.toml", b"client"),
+            ("overrides/config/b.toml", b"b"),
+        ]);
+
+        let limits = build::archive::Limits::default();
+
+        assert!(extract_overrides(
+            &mut archive,
+            &["overrides", "server-overrides"],
+            &dest,
+            limits
+        )
+        .unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("config/a.toml")).unwrap(),
+            "server"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dest.join("config/b.toml")).unwrap(),
+            "b"
+        );
+        assert!(!dest.join("modrinth.index.json").exists());
+
+        let limits = build::archive::Limits {
+            max_entries: 10,
+            max_size: 1024,
+        };
+        let mut archive = pack(&[("overrides/a", &[0; 512]), ("overrides/b", &[0; 1024])]);
+
+        assert!(matches!(
+            extract_overrides(
+                &mut archive,
+                &["overrides"],
+                &temp.path().join("bomb"),
+                limits
+            ),
+            Err(ModpackError::Archive(
+                build::archive::ArchiveError::TooLarge(_)
+            ))
+        ));
+    }
+}
@@ -0,0 +1,186 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{info, warn};
+
+use super::{read_json, safe_path, write_template, Loader, ModpackError, Pack};
+use crate::template::{resource::GenericResource, Template};
+
+const INDEX_FILE_NAME: &str = "modrinth.index.json";
+const OVERRIDE_DIRS: &[&str] = &["overrides", "server-overrides"];
+
+#[derive(Debug, Deserialize)]
+struct FileEnv {
+    server: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexFile {
+    path: String,
+    hashes: HashMap<String, String>,
+    env: Option<FileEnv>,
+    downloads: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    game: String,
+    name: String,
+    summary: Option<String>,
+    files: Vec<IndexFile>,
+    dependencies: HashMap<String, String>,
+}
+
+/// Picks the mod loader from the pack dependencies
+fn loader(dependencies: &HashMap<String, String>) -> Result<Loader, ModpackError> {
+    for unsupported in ["forge", "neoforge", "quilt-loader"] {
+        if dependencies.contains_key(unsupported) {
+            return Err(ModpackError::UnsupportedLoader(unsupported.to_string()));
+        }
+    }
+
+    Ok(match dependencies.get("fabric-loader") {
+        Some(version) => Loader::Fabric(version.clone()),
+        None => Loader::Vanilla,
+    })
+}
+
+/// Creates a verified remote resource for every server-side file
+fn file_resources(files: Vec<IndexFile>) -> Result<Vec<GenericResource>, ModpackError> {
+    let mut resources = vec![];
+
+    for f in files {
+        if f.env.as_ref().is_some_and(|e| e.server == "unsupported") {
+            info!("Skipping client-only file \"{}\"", f.path);
+            continue;
+        }
+
+        let template_path = safe_path(&f.path)?;
+
+        let mut downloads = f.downloads.into_iter();
+
+        let url = downloads
+            .next()
+            .ok_or_else(|| ModpackError::Invalid(format!("no download for \"{}\"", f.path)))?;
+
+        let sha512 = f.hashes.get("sha512").cloned();
+        let sha1 = f.hashes.get("sha1").cloned();
+
+        if sha512.is_none() && sha1.is_none() {
+            warn!("No hashes for \"{}\", it won't be verified", f.path);
+        }
+
+        resources.push(GenericResource::Remote {
+            url,
+            mirrors: downloads.collect(),
+            user_agent: None,
+            override_name: template_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string()),
+            sha512,
+            sha256: None,
+            sha1,
+            use_variables: None,
+            archive: None,
+            template_path,
+            when: None,
+        });
+    }
+
+    Ok(resources)
+}
+
+/// Imports a Modrinth modpack (`.mrpack`) as a template in the output
+/// directory
+pub async fn import<P: AsRef<Path>, O: AsRef<Path>>(
+    pack: P,
+    output: O,
+) -> Result<Template, ModpackError> {
+    let f = std::fs::File::open(pack).map_err(ModpackError::Filesystem)?;
+    let mut archive = zip::ZipArchive::new(f).map_err(ModpackError::Zip)?;
+
+    let index: Index = read_json(&mut archive, INDEX_FILE_NAME)?;
+
+    if index.game != "minecraft" {
+        return Err(ModpackError::Invalid(format!(
+            "unsupported game \"{}\"",
+            index.game
+        )));
+    }
+
+    let mc_version = index
+        .dependencies
+        .get("minecraft")
+        .ok_or_else(|| ModpackError::Invalid("no Minecraft version".into()))?
+        .clone();
+
+    let pack = Pack {
+        description: index
+            .summary
+            .unwrap_or_else(|| format!("Server for the \"{}\" modpack", index.name)),
+        name: index.name,
+        author: None,
+        mc_version,
+        loader: loader(&index.dependencies)?,
+        files: file_resources(index.files)?,
+    };
+
+    write_template(&mut archive, OVERRIDE_DIRS, pack, output.as_ref()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_resources() {
+        let index: Index = serde_jsonc::from_str(
+            r#"{
+                "formatVersion": 1,
+                "game": "minecraft",
+                "versionId": "1.0.0",
+                "name": "Example Pack",
+                "files": [
+                    {
+                        "path": "mods/lithium.jar",
+                        "hashes": { "sha1": "aa", "sha512": "bb" },
+                        "env": { "client": "required", "server": "required" },
+                        "downloads": ["https://cdn.modrinth.com/data/lithium.jar"],
+                        "fileSize": 1
+                    },
+                    {
+                        "path": "mods/sodium.jar",
+                        "hashes": { "sha512": "cc" },
+                        "env": { "client": "required", "server": "unsupported" },
+                        "downloads": ["https://cdn.modrinth.com/data/sodium.jar"],
+                        "fileSize": 1
+                    }
+                ],
+                "dependencies": { "minecraft": "1.21.4", "fabric-loader": "0.16.9" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            loader(&index.dependencies).unwrap(),
+            Loader::Fabric("0.16.9".into())
+        );
+
+        let resources = file_resources(index.files).unwrap();
+
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].template_path(), Path::new("mods/lithium.jar"));
+
+        let unsafe_file = IndexFile {
+            path: "../escape.jar".into(),
+            hashes: HashMap::new(),
+            env: None,
+            downloads: vec!["https://example.com/escape.jar".into()],
+        };
+
+        assert!(matches!(
+            file_resources(vec![unsafe_file]),
+            Err(ModpackError::UnsafePath(_))
+        ));
+    }
+}
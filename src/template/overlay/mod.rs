@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
 use super::resource::{GenericResource, ServerRuntimeResource};
+use super::var::{VarMap, VarProcessError};
 
 pub mod parse;
 
@@ -17,12 +18,29 @@ pub struct Overlay {
     pub runtime: Option<ServerRuntimeResource>,
     pub resources: Vec<GenericResource>,
     pub saveables: Vec<PathBuf>,
+    /// Directory containing the overlay file, which local resources and the
+    /// include directory are resolved against
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub source_dir: PathBuf,
 }
 
 impl Overlay {
     pub async fn import<P: AsRef<Path>>(file: P) -> Result<Self, parse::Error> {
         parse::file_to_template(file.as_ref()).await
     }
+    /// Substitutes variables in the runtime and resource fields
+    pub async fn substitute_vars(&mut self, vars: &VarMap) -> Result<(), VarProcessError> {
+        if let Some(runtime) = &mut self.runtime {
+            runtime.substitute_vars(vars).await?;
+        }
+
+        for r in &mut self.resources {
+            r.substitute_vars(vars).await?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for Overlay {
@@ -36,6 +54,7 @@ impl Default for Overlay {
             runtime: None,
             resources: vec![],
             saveables: vec![],
+            source_dir: PathBuf::new(),
         }
     }
 }
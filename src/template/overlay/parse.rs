@@ -20,7 +20,15 @@ pub async fn file_to_template<P: AsRef<path::Path>>(path: P) -> Result<Overlay,
         .await
         .map_err(Error::Filesystem)?;
 
-    json_to_template(&json).await
+    let mut template = json_to_template(&json).await?;
+
+    template.source_dir = path
+        .as_ref()
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+
+    Ok(template)
 }
 
 pub async fn json_to_template(json: impl std::fmt::Display) -> Result<Overlay, Error> {
@@ -1,10 +1,32 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path;
+use std::{collections::BTreeMap, path};
 
 use crate::resources;
 
-use super::var::VarFormat;
+use super::{
+    condition::Condition,
+    var::{string_replace, VarFormat, VarMap, VarProcessError},
+};
+
+/// Substitutes variables in a single template field
+async fn replace_field(value: &mut String, vars: &VarMap) -> Result<(), VarProcessError> {
+    *value = string_replace(&value, vars, VarFormat::DollarCurly).await?;
+
+    Ok(())
+}
+
+/// Substitutes variables in a single template path field
+async fn replace_path_field(
+    value: &mut path::PathBuf,
+    vars: &VarMap,
+) -> Result<(), VarProcessError> {
+    *value = string_replace(value.to_string_lossy(), vars, VarFormat::DollarCurly)
+        .await?
+        .into();
+
+    Ok(())
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub enum ServerRuntimeResource {
@@ -20,20 +42,149 @@ pub enum ServerRuntimeResource {
         /// Adds additional arguments for JAR executable
         #[serde(rename = "server-args")]
         server_args: Vec<String>,
+        /// Console command which stops the server gracefully (defaults to
+        /// `stop`, proxies like Velocity use `end`)
+        #[serde(
+            rename = "stop-command",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        stop_command: Option<String>,
     },
 }
 
+impl ServerRuntimeResource {
+    /// Substitutes variables in the version, JAR path and arguments
+    pub async fn substitute_vars(&mut self, vars: &VarMap) -> Result<(), VarProcessError> {
+        match self {
+            ServerRuntimeResource::Jdk {
+                version,
+                jar_path,
+                jdk_args,
+                server_args,
+                stop_command,
+            } => {
+                replace_field(version, vars).await?;
+                replace_path_field(jar_path, vars).await?;
+
+                if let Some(c) = stop_command {
+                    replace_field(c, vars).await?;
+                }
+
+                for a in jdk_args.iter_mut().chain(server_args.iter_mut()) {
+                    replace_field(a, vars).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits applied to the server process when running sandboxed
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ResourceLimits {
+    /// Maximum memory in mebibytes. Derived from the `-Xmx` JDK argument if
+    /// omitted
+    #[serde(rename = "memory-mb", default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    /// Maximum CPU time as a number of cores (e.g. `1.5`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
 pub struct ArchiveInfo {
-    #[serde(rename = "internal-path")]
+    /// Components may contain wildcards (e.g. `mypack-*/server`), each of
+    /// which must match exactly one entry
+    #[serde(rename = "internal-path", default)]
     pub inner_path: path::PathBuf,
-    #[serde(rename = "format")]
-    pub archive_format: resources::ArchiveFormat,
+    /// Leading path components removed from every entry, like tar's
+    /// `--strip-components`
+    #[serde(rename = "strip-components", default, skip_serializing_if = "is_zero")]
+    pub strip_components: usize,
+    /// Only files matching these globs (relative to the internal path) are
+    /// copied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Files matching these globs (relative to the internal path) are skipped
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Detected from the file's contents if omitted
+    #[serde(rename = "format", default, skip_serializing_if = "Option::is_none")]
+    pub archive_format: Option<resources::ArchiveFormat>,
     /// Paths to remove after extraction (relative to the new directory)
-    #[serde(rename = "post-remove")]
+    #[serde(rename = "post-remove", default)]
     pub post_remove: Vec<path::PathBuf>,
 }
 
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub enum PatchFormat {
+    #[serde(rename = "properties")]
+    Properties,
+    #[serde(rename = "yaml")]
+    Yaml,
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "toml")]
+    Toml,
+}
+
+impl PatchFormat {
+    /// Detects the format from a file extension
+    pub fn from_path<P: AsRef<path::Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "properties" => Some(PatchFormat::Properties),
+            "yml" | "yaml" => Some(PatchFormat::Yaml),
+            "json" => Some(PatchFormat::Json),
+            "toml" => Some(PatchFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// Schema for a map of arbitrary values, as `serde_jsonc::Value` doesn't
+/// implement `JsonSchema`
+fn value_map_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    schemars::schema::SchemaObject {
+        instance_type: Some(schemars::schema::InstanceType::Object.into()),
+        object: Some(Box::new(schemars::schema::ObjectValidation {
+            additional_properties: Some(Box::new(schemars::schema::Schema::Bool(true))),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Schema for an arbitrary value, as `serde_jsonc::Value` doesn't implement
+/// `JsonSchema`
+fn value_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    schemars::schema::Schema::Bool(true)
+}
+
+/// Inline text, either as a single string or as a list of lines
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum TextContent {
+    Single(String),
+    Lines(Vec<String>),
+}
+
+impl TextContent {
+    /// Returns the full text. Lists of lines are terminated by a newline.
+    pub fn text(&self) -> String {
+        match self {
+            TextContent::Single(s) => s.clone(),
+            TextContent::Lines(lines) => lines.iter().map(|l| format!("{}\n", l)).collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub enum GenericResource {
     /// A remote file to download via provided URL
@@ -41,6 +192,10 @@ pub enum GenericResource {
     Remote {
         /// URL of the remote file
         url: String,
+        /// Mirror URLs tried in order if the download fails or can't be
+        /// verified
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        mirrors: Vec<String>,
         /// Custom user agent to use for the download
         #[serde(skip_serializing_if = "Option::is_none")]
         #[serde(rename = "user-agent")]
@@ -52,6 +207,13 @@ pub enum GenericResource {
         /// Optional SHA-512 hash of the remote file for verification
         #[serde(skip_serializing_if = "Option::is_none")]
         sha512: Option<String>,
+        /// Optional SHA-256 hash of the remote file for verification
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+        /// Optional SHA-1 hash of the remote file for verification. Only use
+        /// this if no stronger hash is available.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha1: Option<String>,
         /// Whether to use variables in the file
         #[serde(skip_serializing_if = "Option::is_none")]
         #[serde(rename = "use-variables")]
@@ -63,6 +225,9 @@ pub enum GenericResource {
         /// Path the file should be written to inside the build
         #[serde(rename = "template-path")]
         template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
     },
     /// A file encoded with Base64
     #[serde(rename = "base64")]
@@ -75,6 +240,9 @@ pub enum GenericResource {
         /// Path the file should be written to inside the build
         #[serde(rename = "template-path")]
         template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
     },
     /// Copy file from Volkanic include folder to template
     #[serde(rename = "include")]
@@ -88,5 +256,398 @@ pub enum GenericResource {
         /// Path the file should be written to inside the build
         #[serde(rename = "template-path")]
         template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// Copy a file or directory from a path relative to the template file
+    #[serde(rename = "local")]
+    Local {
+        /// Path to the file or directory, relative to the template file
+        path: path::PathBuf,
+        /// Glob patterns of files to copy from a directory, relative to the
+        /// directory. All files are copied if empty.
+        ///
+        /// Example: `["**/*.yml"]`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        include: Vec<String>,
+        /// Glob patterns of files to skip when copying a directory
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        exclude: Vec<String>,
+        /// Whether to use variables in the file. Not supported for directories.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "use-variables")]
+        use_variables: Option<VarFormat>,
+        /// Path the file or directory should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// An artifact from a Maven repository, verified with the repository's
+    /// checksum files
+    #[serde(rename = "maven")]
+    Maven {
+        /// URL of the repository
+        ///
+        /// Example: "https://repo1.maven.org/maven2"
+        repository: String,
+        /// Group ID of the artifact
+        group: String,
+        /// Artifact ID
+        artifact: String,
+        /// Version of the artifact, or "LATEST"/"RELEASE" to use the
+        /// repository's metadata
+        version: String,
+        /// Optional classifier (e.g. "all")
+        #[serde(skip_serializing_if = "Option::is_none")]
+        classifier: Option<String>,
+        /// File extension of the artifact, "jar" if not provided
+        #[serde(skip_serializing_if = "Option::is_none")]
+        extension: Option<String>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
     },
+    /// A plugin from Hangar, verified with the SHA-256 hash provided by
+    /// Hangar
+    #[serde(rename = "hangar")]
+    Hangar {
+        /// Project slug, optionally prefixed with the author
+        ///
+        /// Example: "ViaVersion/ViaVersion"
+        project: String,
+        /// Version name, or the newest version compatible with the platform
+        /// version if not provided
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+        /// Platform the plugin is for
+        #[serde(default)]
+        platform: resources::HangarPlatform,
+        /// Platform version the newest version must be compatible with
+        ///
+        /// Example: "1.21.4"
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "platform-version")]
+        platform_version: Option<String>,
+        /// Channel to take the newest version from, "Release" if not provided
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// A plugin from SpigotMC, downloaded through Spiget
+    ///
+    /// Spiget doesn't provide hashes, so the file can't be verified. Premium
+    /// resources aren't supported.
+    #[serde(rename = "spiget")]
+    Spiget {
+        /// ID of the SpigotMC resource
+        resource: u64,
+        /// ID of the resource version, or the newest version if not provided
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<u64>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// Files from a git repository at a specific commit, tag or branch
+    ///
+    /// Clones are cached in the downloads directory. Requires git to be
+    /// installed.
+    #[serde(rename = "git")]
+    Git {
+        /// URL of the repository (`https`, `http`, `ssh`, `git` or `file`)
+        url: String,
+        /// Commit hash, tag or branch to use
+        #[serde(rename = "ref")]
+        reference: String,
+        /// Optional full commit hash the reference must resolve to for
+        /// verification
+        #[serde(skip_serializing_if = "Option::is_none")]
+        commit: Option<String>,
+        /// Directory inside the repository to copy instead of the whole
+        /// repository
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subdirectory: Option<path::PathBuf>,
+        /// Path the files should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// A UTF-8 text file with inline contents
+    #[serde(rename = "text")]
+    Text {
+        /// Contents of the file, either as a string or as a list of lines
+        content: TextContent,
+        /// Whether to use variables in the file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "use-variables")]
+        use_variables: Option<VarFormat>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// A JSON file serialized from an inline value
+    #[serde(rename = "json")]
+    Json {
+        #[schemars(schema_with = "value_schema")]
+        value: serde_jsonc::Value,
+        /// Whether to use variables in the file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "use-variables")]
+        use_variables: Option<VarFormat>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// A YAML file serialized from an inline value
+    #[serde(rename = "yaml")]
+    Yaml {
+        #[schemars(schema_with = "value_schema")]
+        value: serde_jsonc::Value,
+        /// Whether to use variables in the file
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "use-variables")]
+        use_variables: Option<VarFormat>,
+        /// Path the file should be written to inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+    /// Set or remove keys in a `.properties`, YAML, JSON or TOML file in the
+    /// build. The file is created if it doesn't exist yet.
+    ///
+    /// Comments are preserved in `.properties` and TOML files, but not in
+    /// YAML or JSON files.
+    #[serde(rename = "patch")]
+    Patch {
+        /// Format of the file, detected from the file extension if not provided
+        #[serde(skip_serializing_if = "Option::is_none")]
+        format: Option<PatchFormat>,
+        /// Keys to set. Nested keys are separated with dots, except in
+        /// `.properties` files.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        #[schemars(schema_with = "value_map_schema")]
+        set: BTreeMap<String, serde_jsonc::Value>,
+        /// Keys to remove
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        remove: Vec<String>,
+        /// Path of the file to patch inside the build
+        #[serde(rename = "template-path")]
+        template_path: path::PathBuf,
+        /// Only include the resource if the condition is met
+        #[serde(skip_serializing_if = "Option::is_none")]
+        when: Option<Condition>,
+    },
+}
+
+impl GenericResource {
+    /// Condition under which the resource is included in the build
+    pub fn condition(&self) -> Option<&Condition> {
+        match self {
+            GenericResource::Remote { when, .. }
+            | GenericResource::Base64 { when, .. }
+            | GenericResource::Include { when, .. }
+            | GenericResource::Local { when, .. }
+            | GenericResource::Maven { when, .. }
+            | GenericResource::Hangar { when, .. }
+            | GenericResource::Spiget { when, .. }
+            | GenericResource::Git { when, .. }
+            | GenericResource::Text { when, .. }
+            | GenericResource::Json { when, .. }
+            | GenericResource::Yaml { when, .. }
+            | GenericResource::Patch { when, .. } => when.as_ref(),
+        }
+    }
+    /// Path the resource is written to inside the build
+    pub fn template_path(&self) -> &path::Path {
+        match self {
+            GenericResource::Remote { template_path, .. }
+            | GenericResource::Base64 { template_path, .. }
+            | GenericResource::Include { template_path, .. }
+            | GenericResource::Local { template_path, .. }
+            | GenericResource::Maven { template_path, .. }
+            | GenericResource::Hangar { template_path, .. }
+            | GenericResource::Spiget { template_path, .. }
+            | GenericResource::Git { template_path, .. }
+            | GenericResource::Text { template_path, .. }
+            | GenericResource::Json { template_path, .. }
+            | GenericResource::Yaml { template_path, .. }
+            | GenericResource::Patch { template_path, .. } => template_path,
+        }
+    }
+    /// Substitutes variables in the URL, override name, local path, Maven
+    /// coordinates, Hangar project, git reference, patched string values and
+    /// template path
+    pub async fn substitute_vars(&mut self, vars: &VarMap) -> Result<(), VarProcessError> {
+        match self {
+            GenericResource::Remote {
+                url,
+                mirrors,
+                override_name,
+                template_path,
+                ..
+            } => {
+                replace_field(url, vars).await?;
+
+                for mirror in mirrors {
+                    replace_field(mirror, vars).await?;
+                }
+
+                if let Some(override_name) = override_name {
+                    replace_field(override_name, vars).await?;
+                }
+
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Maven {
+                repository,
+                group,
+                artifact,
+                version,
+                classifier,
+                template_path,
+                ..
+            } => {
+                replace_field(repository, vars).await?;
+                replace_field(group, vars).await?;
+                replace_field(artifact, vars).await?;
+                replace_field(version, vars).await?;
+
+                if let Some(classifier) = classifier {
+                    replace_field(classifier, vars).await?;
+                }
+
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Hangar {
+                project,
+                version,
+                platform_version,
+                channel,
+                template_path,
+                ..
+            } => {
+                replace_field(project, vars).await?;
+
+                for field in [version, platform_version, channel].into_iter().flatten() {
+                    replace_field(field, vars).await?;
+                }
+
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Git {
+                url,
+                reference,
+                template_path,
+                ..
+            } => {
+                replace_field(url, vars).await?;
+                replace_field(reference, vars).await?;
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Local {
+                path,
+                template_path,
+                ..
+            } => {
+                replace_path_field(path, vars).await?;
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Base64 { template_path, .. }
+            | GenericResource::Include { template_path, .. }
+            | GenericResource::Spiget { template_path, .. }
+            | GenericResource::Text { template_path, .. }
+            | GenericResource::Json { template_path, .. }
+            | GenericResource::Yaml { template_path, .. } => {
+                replace_path_field(template_path, vars).await?;
+            }
+            GenericResource::Patch {
+                set, template_path, ..
+            } => {
+                for value in set.values_mut() {
+                    if let serde_jsonc::Value::String(s) = value {
+                        replace_field(s, vars).await?;
+                    }
+                }
+
+                replace_path_field(template_path, vars).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_substitute_vars() {
+        let vars = VarMap::from([("MC_VERSION".to_string(), "1.21.4".to_string())]);
+
+        let mut resource = GenericResource::Remote {
+            url: "https://example.com/${MC_VERSION}/server.jar".into(),
+            mirrors: vec!["https://mirror.example.com/${MC_VERSION}/server.jar".into()],
+            user_agent: None,
+            override_name: Some("server-${MC_VERSION}.jar".into()),
+            sha512: None,
+            sha256: None,
+            sha1: None,
+            use_variables: None,
+            archive: None,
+            template_path: "versions/${MC_VERSION}/server.jar".into(),
+            when: None,
+        };
+
+        resource.substitute_vars(&vars).await.unwrap();
+
+        match resource {
+            GenericResource::Remote {
+                url,
+                mirrors,
+                override_name,
+                template_path,
+                ..
+            } => {
+                assert_eq!(url, "https://example.com/1.21.4/server.jar");
+                assert_eq!(mirrors, ["https://mirror.example.com/1.21.4/server.jar"]);
+                assert_eq!(override_name.unwrap(), "server-1.21.4.jar");
+                assert_eq!(template_path, path::Path::new("versions/1.21.4/server.jar"));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_text_content() {
+        let single: TextContent = serde_jsonc::from_str("\"eula=true\"").unwrap();
+        let lines: TextContent = serde_jsonc::from_str("[\"# EULA\", \"eula=true\"]").unwrap();
+
+        assert_eq!(single.text(), "eula=true");
+        assert_eq!(lines.text(), "# EULA\neula=true\n");
+    }
 }
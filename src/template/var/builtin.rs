@@ -0,0 +1,77 @@
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::hostinfo;
+
+use super::{super::Template, VarMap};
+
+/// Total host memory in mebibytes
+pub const HOST_MEMORY_MB: &str = "host.memory_mb";
+/// Number of logical CPUs
+pub const HOST_CPUS: &str = "host.cpus";
+/// Host operating system (e.g. "linux")
+pub const HOST_OS: &str = "host.os";
+/// Host architecture (e.g. "amd64")
+pub const HOST_ARCH: &str = "host.arch";
+/// Seconds since the Unix epoch at the start of the build
+pub const BUILD_TIMESTAMP: &str = "build.timestamp";
+/// Random UUID generated for each build
+pub const RANDOM_UUID: &str = "random.uuid";
+/// Random password generated for each build
+pub const RANDOM_PASSWORD: &str = "random.password";
+/// Name of the template
+pub const TEMPLATE_NAME: &str = "template.name";
+/// Version of the template (e.g. "1.0.0"), empty if unversioned
+pub const TEMPLATE_VERSION: &str = "template.version";
+
+/// Creates the built-in variables available to every template
+pub async fn builtins(template: &Template) -> VarMap {
+    let mut vars = VarMap::new();
+
+    vars.insert(
+        HOST_MEMORY_MB.into(),
+        (hostinfo::total_memory().await / 1024 / 1024).to_string(),
+    );
+    vars.insert(HOST_CPUS.into(), hostinfo::cpu_count().await.to_string());
+
+    if let Some(os) = hostinfo::Os::get().await {
+        vars.insert(HOST_OS.into(), os.to_string());
+    }
+    if let Some(arch) = hostinfo::Arch::get().await {
+        vars.insert(HOST_ARCH.into(), arch.to_string());
+    }
+
+    vars.insert(
+        BUILD_TIMESTAMP.into(),
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .to_string(),
+    );
+    vars.insert(RANDOM_UUID.into(), Uuid::new_v4().to_string());
+    vars.insert(RANDOM_PASSWORD.into(), Uuid::new_v4().simple().to_string());
+
+    vars.insert(TEMPLATE_NAME.into(), template.name.clone());
+    vars.insert(
+        TEMPLATE_VERSION.into(),
+        match template.version {
+            Some((major, minor, patch)) => [Some(major), minor, patch]
+                .iter()
+                .flatten()
+                .map(|n| n.to_string())
+                .collect::<Vec<String>>()
+                .join("."),
+            None => String::new(),
+        },
+    );
+
+    for (name, value) in &vars {
+        // Avoid leaking the generated password into logs
+        if name != RANDOM_PASSWORD {
+            debug!("Built-in variable \"{}\" is \"{}\"", name, value);
+        }
+    }
+
+    vars
+}
@@ -0,0 +1,514 @@
+//! Placeholder expressions used during variable substitution
+//!
+//! The body of a placeholder (the part between `${` and `}`) has the form:
+//!
+//! ```text
+//! NAME[+N|-N ...][:-DEFAULT][|FILTER ...]
+//! ```
+//!
+//! The value of `NAME` is looked up first, falling back to `DEFAULT` if the
+//! variable is undefined or empty. Integer offsets are applied next, followed
+//! by each filter from left to right. A backslash escapes the next character
+//! inside the default value (e.g. `\}` or `\|`). A `-` followed by a digit
+//! starts an offset, otherwise it is part of the name (e.g. `SERVER-PORT`).
+
+use tracing::{debug, warn};
+
+use super::VarMap;
+
+const PLACEHOLDER_START: &str = "${";
+const PLACEHOLDER_END: char = '}';
+const DEFAULT_SEPARATOR: &str = ":-";
+const FILTER_SEPARATOR: char = '|';
+const ESCAPE: char = '\\';
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExprError {
+    #[error("Missing variable name")]
+    MissingName,
+    #[error("Unexpected character: '{0}'")]
+    UnexpectedChar(char),
+    #[error("Invalid integer offset: \"{0}\"")]
+    InvalidOffset(String),
+    #[error("Unknown filter: \"{0}\"")]
+    UnknownFilter(String),
+    #[error("Value is not an integer: \"{0}\"")]
+    NotAnInteger(String),
+    #[error("Integer overflow")]
+    Overflow,
+    #[error("Undefined variable: \"{0}\"")]
+    Undefined(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Converts the value to uppercase
+    Upper,
+    /// Converts the value to lowercase
+    Lower,
+    /// Removes leading and trailing whitespace
+    Trim,
+    /// Encodes the value as a JSON literal. Numbers and booleans are left
+    /// bare, anything else becomes a quoted string.
+    Json,
+}
+
+impl Filter {
+    fn parse(name: &str) -> Result<Self, ExprError> {
+        match name.trim() {
+            "upper" => Ok(Filter::Upper),
+            "lower" => Ok(Filter::Lower),
+            "trim" => Ok(Filter::Trim),
+            "json" => Ok(Filter::Json),
+            other => Err(ExprError::UnknownFilter(other.to_string())),
+        }
+    }
+    fn apply(&self, value: String) -> String {
+        match self {
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::Json => {
+                let is_scalar = value == "true"
+                    || value == "false"
+                    || serde_jsonc::from_str::<serde_jsonc::Number>(&value).is_ok();
+
+                if is_scalar {
+                    value
+                } else {
+                    serde_jsonc::Value::String(value).to_string()
+                }
+            }
+        }
+    }
+}
+
+/// A parsed placeholder expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    pub name: String,
+    pub offset: i64,
+    pub default: Option<String>,
+    pub filters: Vec<Filter>,
+}
+
+impl Expr {
+    /// Parses the body of a placeholder (without the surrounding `${` and `}`)
+    pub fn parse(body: &str) -> Result<Self, ExprError> {
+        let name_len = name_len(body);
+
+        if name_len == 0 {
+            return Err(ExprError::MissingName);
+        }
+
+        let mut expr = Expr {
+            name: body[..name_len].to_string(),
+            offset: 0,
+            default: None,
+            filters: vec![],
+        };
+
+        let mut rest = &body[name_len..];
+
+        // Integer offsets, e.g. `+1` or `-10`
+        while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let digits_len = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len() - 1);
+            let digits = &rest[1..1 + digits_len];
+
+            let n = digits
+                .parse::<i64>()
+                .map_err(|_| ExprError::InvalidOffset(format!("{}{}", sign, digits)))?;
+
+            expr.offset = if sign == '+' {
+                expr.offset.checked_add(n)
+            } else {
+                expr.offset.checked_sub(n)
+            }
+            .ok_or(ExprError::Overflow)?;
+
+            rest = &rest[1 + digits_len..];
+        }
+
+        // Default value, terminated by the first unescaped filter separator
+        if let Some(after) = rest.strip_prefix(DEFAULT_SEPARATOR) {
+            let mut default = String::new();
+            let mut chars = after.char_indices();
+            let mut consumed = after.len();
+
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    ESCAPE => {
+                        if let Some((_, escaped)) = chars.next() {
+                            default.push(escaped);
+                        }
+                    }
+                    FILTER_SEPARATOR => {
+                        consumed = i;
+                        break;
+                    }
+                    c => default.push(c),
+                }
+            }
+
+            expr.default = Some(default);
+            rest = &after[consumed..];
+        }
+
+        // Filters
+        if !rest.is_empty() {
+            match rest.strip_prefix(FILTER_SEPARATOR) {
+                Some(filters) => {
+                    for f in filters.split(FILTER_SEPARATOR) {
+                        expr.filters.push(Filter::parse(f)?);
+                    }
+                }
+                None => {
+                    // `unwrap()` is safe as `rest` was checked to not be empty
+                    return Err(ExprError::UnexpectedChar(rest.chars().next().unwrap()));
+                }
+            }
+        }
+
+        Ok(expr)
+    }
+    /// Evaluates the expression against the given variables
+    ///
+    /// Returns `None` if the variable is undefined and no default is provided.
+    pub fn evaluate(&self, vars: &VarMap) -> Result<Option<String>, ExprError> {
+        let value = match vars.get(&self.name) {
+            Some(v) if !v.is_empty() || self.default.is_none() => v.clone(),
+            _ => match &self.default {
+                Some(d) => d.clone(),
+                None => return Ok(None),
+            },
+        };
+
+        let mut value = if self.offset != 0 {
+            let n = value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| ExprError::NotAnInteger(value.clone()))?;
+
+            n.checked_add(self.offset)
+                .ok_or(ExprError::Overflow)?
+                .to_string()
+        } else {
+            value
+        };
+
+        for f in &self.filters {
+            value = f.apply(value);
+        }
+
+        Ok(Some(value))
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Length of the leading variable name of a placeholder body
+///
+/// A `-` belongs to the name (e.g. `SERVER-PORT`) unless a digit follows it,
+/// in which case it starts an offset.
+fn name_len(body: &str) -> usize {
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let in_name = match c {
+            '-' => chars
+                .peek()
+                .is_some_and(|(_, next)| is_name_char(*next) && !next.is_ascii_digit()),
+            c => is_name_char(c),
+        };
+
+        if !in_name {
+            return i;
+        }
+    }
+
+    body.len()
+}
+
+/// A segment of text split by the tokenizer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// Text outside of any placeholder
+    Text(&'a str),
+    /// A placeholder, with `raw` including the delimiters and `body` excluding them
+    Placeholder { raw: &'a str, body: &'a str },
+}
+
+/// Splits text into literal text and placeholders
+///
+/// An unterminated placeholder is treated as literal text.
+pub fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = input;
+
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let body_start = start + PLACEHOLDER_START.len();
+
+        let mut end = None;
+        let mut chars = rest[body_start..].char_indices();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                ESCAPE => {
+                    chars.next();
+                }
+                PLACEHOLDER_END => {
+                    end = Some(body_start + i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let end = match end {
+            Some(end) => end,
+            None => break,
+        };
+
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+
+        tokens.push(Token::Placeholder {
+            raw: &rest[start..=end],
+            body: &rest[body_start..end],
+        });
+
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    tokens
+}
+
+/// Returns the leading variable name of a placeholder body
+pub fn leading_name(body: &str) -> &str {
+    &body[..name_len(body)]
+}
+
+/// Returns the names of all variables referenced by placeholders in `input`
+pub fn references(input: &str) -> Vec<&str> {
+    tokenize(input)
+        .into_iter()
+        .filter_map(|t| match t {
+            Token::Placeholder { body, .. } => Some(leading_name(body)),
+            Token::Text(_) => None,
+        })
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// Replaces placeholders in `input` with their evaluated values
+///
+/// Placeholders which don't refer to a defined variable and have no default
+/// are left untouched, as are placeholders which fail to parse. The latter
+/// are only reported with a warning if they start with the name of a defined
+/// variable.
+pub fn substitute(input: &str, vars: &VarMap) -> Result<String, (String, ExprError)> {
+    substitute_inner(input, vars, false)
+}
+
+/// Like [`substitute`], but fails on any placeholder which can't be resolved
+pub fn substitute_strict(input: &str, vars: &VarMap) -> Result<String, (String, ExprError)> {
+    substitute_inner(input, vars, true)
+}
+
+fn substitute_inner(
+    input: &str,
+    vars: &VarMap,
+    strict: bool,
+) -> Result<String, (String, ExprError)> {
+    let mut result = String::with_capacity(input.len());
+
+    for token in tokenize(input) {
+        match token {
+            Token::Text(t) => result.push_str(t),
+            Token::Placeholder { raw, body } => {
+                let expr = match Expr::parse(body) {
+                    Ok(expr) => expr,
+                    Err(e) => {
+                        if strict {
+                            return Err((raw.to_string(), e));
+                        } else if vars.contains_key(leading_name(body)) {
+                            warn!("Leaving invalid placeholder \"{}\" as-is: {}", raw, e);
+                        } else {
+                            debug!("Leaving unparsable placeholder \"{}\" as-is: {}", raw, e);
+                        }
+
+                        result.push_str(raw);
+                        continue;
+                    }
+                };
+
+                match expr.evaluate(vars).map_err(|e| (raw.to_string(), e))? {
+                    Some(value) => {
+                        debug!("Replacing \"{}\" with \"{}\"", raw, value);
+                        result.push_str(&value);
+                    }
+                    None if strict => {
+                        return Err((raw.to_string(), ExprError::Undefined(expr.name)))
+                    }
+                    None => result.push_str(raw),
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> VarMap {
+        VarMap::from([
+            ("PORT".to_string(), "25565".to_string()),
+            ("SERVER-PORT".to_string(), "25566".to_string()),
+            ("NAME".to_string(), "Steve".to_string()),
+            ("MOTD".to_string(), "".to_string()),
+            ("PLAYERS".to_string(), "20".to_string()),
+            ("QUOTE".to_string(), "say \"hi\"".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("port=${PORT}\nname=${NAME|upper} ${open"),
+            vec![
+                Token::Text("port="),
+                Token::Placeholder {
+                    raw: "${PORT}",
+                    body: "PORT"
+                },
+                Token::Text("\nname="),
+                Token::Placeholder {
+                    raw: "${NAME|upper}",
+                    body: "NAME|upper"
+                },
+                Token::Text(" ${open"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Expr::parse("PORT+1-3:-A \\| B|trim|json").unwrap(),
+            Expr {
+                name: "PORT".into(),
+                offset: -2,
+                default: Some("A | B".into()),
+                filters: vec![Filter::Trim, Filter::Json],
+            }
+        );
+
+        assert_eq!(
+            Expr::parse("SERVER-PORT-1|json").unwrap(),
+            Expr {
+                name: "SERVER-PORT".into(),
+                offset: -1,
+                default: None,
+                filters: vec![Filter::Json],
+            }
+        );
+
+        assert!(matches!(Expr::parse(":-x"), Err(ExprError::MissingName)));
+        assert!(matches!(
+            Expr::parse("NAME|shout"),
+            Err(ExprError::UnknownFilter(_))
+        ));
+        assert!(matches!(
+            Expr::parse("NAME x"),
+            Err(ExprError::UnexpectedChar(' '))
+        ));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let vars = vars();
+
+        assert_eq!(substitute("${PORT}", &vars).unwrap(), "25565");
+        assert_eq!(substitute("${PORT+1}", &vars).unwrap(), "25566");
+        assert_eq!(substitute("${SERVER-PORT}", &vars).unwrap(), "25566");
+        assert_eq!(substitute("${PORT-1}", &vars).unwrap(), "25564");
+        assert_eq!(substitute("${NAME|upper}", &vars).unwrap(), "STEVE");
+        assert_eq!(
+            substitute("${MOTD:-A Minecraft Server}", &vars).unwrap(),
+            "A Minecraft Server"
+        );
+        assert_eq!(substitute("${MISSING:-25565+1}", &vars).unwrap(), "25565+1");
+        assert_eq!(substitute("${MISSING+1:-25565}", &vars).unwrap(), "25566");
+        assert_eq!(substitute("${PLAYERS|json}", &vars).unwrap(), "20");
+        assert_eq!(
+            substitute("${QUOTE|json}", &vars).unwrap(),
+            "\"say \\\"hi\\\"\""
+        );
+    }
+
+    #[test]
+    fn test_substitute_untouched() {
+        let vars = vars();
+
+        assert_eq!(substitute("${MISSING}", &vars).unwrap(), "${MISSING}");
+        assert_eq!(
+            substitute("${ not a var }", &vars).unwrap(),
+            "${ not a var }"
+        );
+        assert_eq!(substitute("cost: $5 {x}", &vars).unwrap(), "cost: $5 {x}");
+        assert_eq!(
+            substitute("${PORT something}", &vars).unwrap(),
+            "${PORT something}"
+        );
+        assert_eq!(substitute("${NAME|shout}", &vars).unwrap(), "${NAME|shout}");
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(
+            references("${PORT+1} ${NAME|upper} ${:-x} $PORT"),
+            vec!["PORT", "NAME"]
+        );
+    }
+
+    #[test]
+    fn test_substitute_strict() {
+        let vars = vars();
+
+        assert_eq!(substitute_strict("${PORT+1}", &vars).unwrap(), "25566");
+        assert!(matches!(
+            substitute_strict("${MISSING}", &vars),
+            Err((_, ExprError::Undefined(_)))
+        ));
+        assert!(matches!(
+            substitute_strict("${ not a var }", &vars),
+            Err((_, ExprError::MissingName))
+        ));
+    }
+
+    #[test]
+    fn test_substitute_errors() {
+        let vars = vars();
+
+        assert!(matches!(
+            substitute("${NAME+1}", &vars),
+            Err((_, ExprError::NotAnInteger(_)))
+        ));
+        assert!(matches!(
+            substitute_strict("${NAME|shout}", &vars),
+            Err((_, ExprError::UnknownFilter(_)))
+        ));
+    }
+}
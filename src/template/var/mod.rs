@@ -0,0 +1,237 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+pub mod builtin;
+pub mod expr;
+
+pub type EnvMap = HashMap<String, String>;
+pub type VarMap = HashMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Var {
+    #[serde(rename = "static")]
+    Static { name: String, value: String },
+    #[serde(rename = "user")]
+    User {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        default: Option<String>,
+    },
+    /// Variable computed from other variables and built-ins
+    ///
+    /// The value uses the same placeholder syntax as files, e.g.
+    /// `${PORT+1}` or `${random.password}`. Computed variables may reference
+    /// each other in any order, as long as there are no cycles.
+    #[serde(rename = "computed")]
+    Computed { name: String, value: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum VarFormat {
+    #[serde(rename = "dollar-curly")]
+    DollarCurly,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VarProcessError {
+    #[error("User dynamic variable not found: {0}")]
+    UserVarNotFound(String),
+    #[error("Provided user variable does not have name")]
+    RawVarWithoutName,
+    #[error("Provided user variable does not have value")]
+    RawVarWithoutValue,
+    #[error("Invalid variable expression \"{0}\": {1}")]
+    Expression(String, expr::ExprError),
+    #[error("Failed to compute variable \"{0}\": {1}")]
+    Computed(String, Box<VarProcessError>),
+    #[error("Cyclic variable dependency: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+/// Replaces all variable placeholders in `value`
+///
+/// See [`expr`] for the supported placeholder syntax.
+pub async fn string_replace(
+    value: impl std::fmt::Display,
+    vars: &VarMap,
+    format: VarFormat,
+) -> Result<String, VarProcessError> {
+    match format {
+        VarFormat::DollarCurly => expr::substitute(&value.to_string(), vars)
+            .map_err(|(raw, e)| VarProcessError::Expression(raw, e)),
+    }
+}
+
+pub async fn process_vars(
+    template_vars: &mut VarMap,
+    vars: Vec<Var>,
+    env_defs: &EnvMap,
+) -> Result<(), VarProcessError> {
+    let mut computed = vec![];
+
+    for v in vars {
+        match v {
+            Var::Static { name, value } => {
+                template_vars.insert(name, value);
+            }
+            Var::User { name, default } => {
+                template_vars.insert(name.clone(), {
+                    match env_defs.get(&name) {
+                        Some(v) => {
+                            debug!("Variable {} defined as \"{}\"", name, v);
+
+                            v.to_string()
+                        }
+                        None => match default {
+                            Some(v) => {
+                                info!(
+                                    "Variable \"{}\" not defined, using default: \"{}\"",
+                                    name, v
+                                );
+                                v.to_string()
+                            }
+                            None => return Err(VarProcessError::UserVarNotFound(name)),
+                        },
+                    }
+                });
+            }
+            Var::Computed { name, value } => computed.push((name, value)),
+        }
+    }
+
+    // Computed variables are evaluated after all others, in dependency order
+    for (name, value) in computed_order(&computed)? {
+        let result = expr::substitute_strict(value, template_vars).map_err(|(raw, e)| {
+            VarProcessError::Computed(
+                name.to_string(),
+                Box::new(VarProcessError::Expression(raw, e)),
+            )
+        })?;
+
+        debug!("Computed variable {}", name);
+
+        template_vars.insert(name.to_string(), result);
+    }
+
+    Ok(())
+}
+
+/// Sorts computed variables so each comes after the computed variables it
+/// references
+fn computed_order(computed: &[(String, String)]) -> Result<Vec<(&str, &str)>, VarProcessError> {
+    fn visit<'a>(
+        name: &'a str,
+        computed: &HashMap<&'a str, &'a str>,
+        done: &mut Vec<&'a str>,
+        path: &mut Vec<&'a str>,
+    ) -> Result<(), VarProcessError> {
+        if done.contains(&name) {
+            return Ok(());
+        }
+
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+            cycle.push(name.to_string());
+
+            return Err(VarProcessError::Cycle(cycle));
+        }
+
+        path.push(name);
+
+        for dep in expr::references(computed[name]) {
+            if let Some((dep, _)) = computed.get_key_value(dep) {
+                visit(dep, computed, done, path)?;
+            }
+        }
+
+        path.pop();
+        done.push(name);
+
+        Ok(())
+    }
+
+    let lookup: HashMap<&str, &str> = computed
+        .iter()
+        .map(|(n, v)| (n.as_str(), v.as_str()))
+        .collect();
+
+    let mut done = vec![];
+
+    for (name, _) in computed {
+        visit(name, &lookup, &mut done, &mut vec![])?;
+    }
+
+    Ok(done.into_iter().map(|n| (n, lookup[n])).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_string_replace() {
+        let vars = VarMap::from([
+            ("PORT".to_string(), "25565".to_string()),
+            ("MOTD".to_string(), "".to_string()),
+        ]);
+
+        assert_eq!(
+            string_replace(
+                "server-port=${PORT}\nquery.port=${PORT+1}\nmotd=${MOTD:-A Minecraft Server}\nlevel-name=${LEVEL}",
+                &vars,
+                VarFormat::DollarCurly,
+            )
+            .await
+            .unwrap(),
+            "server-port=25565\nquery.port=25566\nmotd=A Minecraft Server\nlevel-name=${LEVEL}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_vars_computed() {
+        let vars = vec![
+            Var::Computed {
+                name: "QUERY_PORT".into(),
+                value: "${RCON_PORT-1}".into(),
+            },
+            Var::Computed {
+                name: "RCON_PORT".into(),
+                value: "${PORT+10}".into(),
+            },
+            Var::User {
+                name: "PORT".into(),
+                default: Some("25565".into()),
+            },
+        ];
+
+        let mut template_vars = VarMap::new();
+        process_vars(&mut template_vars, vars, &EnvMap::new())
+            .await
+            .unwrap();
+
+        assert_eq!(template_vars["RCON_PORT"], "25575");
+        assert_eq!(template_vars["QUERY_PORT"], "25574");
+    }
+
+    #[tokio::test]
+    async fn test_process_vars_cycle() {
+        let vars = vec![
+            Var::Computed {
+                name: "A".into(),
+                value: "${B}".into(),
+            },
+            Var::Computed {
+                name: "B".into(),
+                value: "${A}".into(),
+            },
+        ];
+
+        let err = process_vars(&mut VarMap::new(), vars, &EnvMap::new())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, VarProcessError::Cycle(c) if c == ["A", "B", "A"]));
+    }
+}
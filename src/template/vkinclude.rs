@@ -1,23 +1,47 @@
 use std::path;
+use tracing::warn;
 
 const INCLUDE_DIR_NAME: &str = ".vkinclude";
 
 #[derive(Clone, Debug)]
 pub struct VolkanicInclude {
     path: path::PathBuf,
+    /// Include directory in the current directory, used before include
+    /// directories were resolved next to the template
+    legacy_path: Option<path::PathBuf>,
 }
 
 impl VolkanicInclude {
-    pub async fn new() -> Self {
+    /// Uses the include directory next to the template file
+    pub async fn new<P: AsRef<path::Path>>(template_dir: P) -> Self {
         Self {
-            path: path::PathBuf::from(INCLUDE_DIR_NAME),
+            path: template_dir.as_ref().join(INCLUDE_DIR_NAME),
+            legacy_path: Some(path::PathBuf::from(INCLUDE_DIR_NAME)),
+        }
+    }
+    pub async fn override_path<P: AsRef<path::Path>>(self, path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            legacy_path: None,
         }
     }
     pub async fn get(&self, id: impl std::fmt::Display) -> Option<path::PathBuf> {
         let p = self.path.join(id.to_string());
 
         if p.exists() {
-            Some(p)
+            return Some(p);
+        }
+
+        let legacy = self.legacy_path.as_ref()?.join(id.to_string());
+
+        if legacy.exists() {
+            warn!(
+                "Using \"{}\" from the current directory. This is deprecated, move it to \"{}\" or pass --include-dir",
+                legacy.display(),
+                self.path.display()
+            );
+
+            Some(legacy)
         } else {
             None
         }
@@ -0,0 +1,570 @@
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{fs, task::spawn_blocking};
+use tracing::{debug, info, warn};
+
+use crate::resources::conf::FILE_BUFFER_SIZE;
+
+/// Environment variable with the shared cache directory
+pub const CACHE_DIR_ENV: &str = "VKCONSTRUCT_CACHE_DIR";
+
+const CACHE_OBJECTS_SUFFIX: &str = "objects/";
+const CACHE_ENTRY_EXTENSION: &str = "json";
+const CACHE_TEMP_EXTENSION: &str = "tmp";
+const CACHE_LOCK_EXTENSION: &str = "lock";
+
+/// Time between attempts to lock an index entry
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+/// Age after which a lock is assumed to be left behind by a crashed process
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("Filesystem error: {0}")]
+    Filesystem(std::io::Error),
+    #[error("Failed to serialize cache entry: {0}")]
+    Json(serde_jsonc::Error),
+    #[error("Invalid size: {0}")]
+    InvalidSize(String),
+}
+
+/// Index information of a cached file
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheEntry {
+    pub sha512: String,
+    pub sha256: String,
+    pub sha1: String,
+    pub size: u64,
+    /// URLs the file was downloaded from
+    pub urls: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub last_used: u64,
+    /// Build directories which used the file
+    pub builds: Vec<PathBuf>,
+}
+
+impl CacheEntry {
+    /// Whether any of the file's hashes match
+    pub fn matches(&self, hash: &str) -> bool {
+        [&self.sha512, &self.sha256, &self.sha1]
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(hash))
+    }
+    /// Time since the file was last used
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.last_used))
+    }
+}
+
+/// Result of a garbage collection
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcSummary {
+    pub removed: usize,
+    pub freed: u64,
+    pub remaining: u64,
+}
+
+/// Download cache shared by multiple stores, with files named by their
+/// actual SHA-512 hash
+///
+/// Every file has an index entry next to it, so concurrent builds never
+/// write to the same index file unless they download the same file.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    pub path: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+    fn objects_path(&self) -> PathBuf {
+        self.path.join(CACHE_OBJECTS_SUFFIX)
+    }
+    /// Path of a cached file
+    pub fn object_path(&self, sha512: &str) -> PathBuf {
+        self.objects_path().join(sha512)
+    }
+    fn entry_path(&self, sha512: &str) -> PathBuf {
+        self.object_path(sha512)
+            .with_extension(CACHE_ENTRY_EXTENSION)
+    }
+    /// Returns the index entries of all cached files
+    pub async fn entries(&self) -> Result<Vec<CacheEntry>, CacheError> {
+        let mut entries = vec![];
+
+        let mut dir = match fs::read_dir(self.objects_path()).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(CacheError::Filesystem(e)),
+        };
+
+        while let Some(e) = dir.next_entry().await.map_err(CacheError::Filesystem)? {
+            let p = e.path();
+
+            if p.extension().and_then(|e| e.to_str()) != Some(CACHE_ENTRY_EXTENSION) {
+                continue;
+            }
+
+            match read_entry(&p).await {
+                Some(entry) => entries.push(entry),
+                None => warn!("Skipping unreadable cache entry \"{}\"", p.display()),
+            }
+        }
+
+        Ok(entries)
+    }
+    /// Finds a cached file by its SHA-512, SHA-256 or SHA-1 hash
+    pub async fn find(&self, hash: &str) -> Result<Option<CacheEntry>, CacheError> {
+        let hash = hash.to_lowercase();
+
+        // SHA-512 hashes are the file names, so no search is necessary
+        if hash.len() == 128 {
+            return Ok(read_entry(&self.entry_path(&hash))
+                .await
+                .filter(|_| self.object_path(&hash).is_file()));
+        }
+
+        Ok(self
+            .entries()
+            .await?
+            .into_iter()
+            .find(|e| e.matches(&hash) && self.object_path(&e.sha512).is_file()))
+    }
+    /// Records that a build used a cached file
+    pub async fn touch(
+        &self,
+        entry: CacheEntry,
+        url: &str,
+        build: &Path,
+    ) -> Result<CacheEntry, CacheError> {
+        let build = std::fs::canonicalize(build).unwrap_or_else(|_| build.to_path_buf());
+
+        self.update_entry(entry, |entry| {
+            entry.last_used = now();
+
+            if !entry.urls.iter().any(|u| u == url) {
+                entry.urls.push(url.to_string());
+            }
+
+            if !entry.builds.contains(&build) {
+                entry.builds.push(build);
+            }
+        })
+        .await
+    }
+    /// Moves a downloaded file into the cache and returns its new path
+    pub async fn insert(
+        &self,
+        file: &Path,
+        url: &str,
+        build: &Path,
+    ) -> Result<PathBuf, CacheError> {
+        let (sha512, sha256, sha1, size) = hash_file(file.to_path_buf())
+            .await
+            .map_err(CacheError::Filesystem)?;
+
+        fs::create_dir_all(self.objects_path())
+            .await
+            .map_err(CacheError::Filesystem)?;
+
+        let object = self.object_path(&sha512);
+
+        if object.is_file() {
+            debug!("\"{}\" is already cached", file.display());
+
+            fs::remove_file(file)
+                .await
+                .map_err(CacheError::Filesystem)?;
+        } else if fs::rename(file, &object).await.is_err() {
+            // The cache may be on a different filesystem, so copy it in
+            // under a temporary name to never expose a partial file
+            let temp =
+                object.with_extension(format!("{}.{}", uuid::Uuid::new_v4(), CACHE_TEMP_EXTENSION));
+
+            fs::copy(file, &temp)
+                .await
+                .map_err(CacheError::Filesystem)?;
+            fs::rename(&temp, &object)
+                .await
+                .map_err(CacheError::Filesystem)?;
+            fs::remove_file(file)
+                .await
+                .map_err(CacheError::Filesystem)?;
+        }
+
+        let entry = CacheEntry {
+            sha512,
+            sha256,
+            sha1,
+            size,
+            urls: vec![],
+            last_used: now(),
+            builds: vec![],
+        };
+
+        // The file is already in place, so a stale entry isn't fatal
+        if let Err(e) = self.touch(entry, url, build).await {
+            warn!(
+                "Failed to update cache entry for \"{}\": {}",
+                object.display(),
+                e
+            );
+        }
+
+        Ok(object)
+    }
+    /// Removes a cached file and its index entry
+    pub async fn remove(&self, entry: &CacheEntry) -> Result<(), CacheError> {
+        for p in [
+            self.object_path(&entry.sha512),
+            self.entry_path(&entry.sha512),
+        ] {
+            if p.is_file() {
+                fs::remove_file(p).await.map_err(CacheError::Filesystem)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Removes files unused for longer than `older_than`, then the least
+    /// recently used files until the cache is no larger than `max_size`
+    pub async fn gc(
+        &self,
+        max_size: Option<u64>,
+        older_than: Option<Duration>,
+    ) -> Result<GcSummary, CacheError> {
+        let mut entries = self.entries().await?;
+        let mut summary = GcSummary::default();
+
+        // Least recently used first
+        entries.sort_by_key(|e| e.last_used);
+
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+        let cutoff = older_than.map(|d| now().saturating_sub(d.as_secs()));
+
+        for entry in entries {
+            let expired = cutoff.is_some_and(|c| entry.last_used < c);
+            let oversized = max_size.is_some_and(|m| total > m);
+
+            if !expired && !oversized {
+                // Drop references to builds which no longer exist
+                if entry.builds.iter().any(|b| !b.is_dir()) {
+                    self.update_entry(entry, |entry| entry.builds.retain(|b| b.is_dir()))
+                        .await?;
+                }
+
+                continue;
+            }
+
+            info!("Removing cached file \"{}\"", entry.sha512);
+
+            self.remove(&entry).await?;
+
+            total -= entry.size;
+            summary.removed += 1;
+            summary.freed += entry.size;
+        }
+
+        summary.remaining = total;
+
+        Ok(summary)
+    }
+    /// Checks every cached file against its hash, removing corrupted files
+    ///
+    /// Returns the removed entries.
+    pub async fn verify(&self) -> Result<Vec<CacheEntry>, CacheError> {
+        let mut corrupted = vec![];
+
+        for entry in self.entries().await? {
+            let object = self.object_path(&entry.sha512);
+
+            let valid = match hash_file(object.clone()).await {
+                Ok((sha512, ..)) => sha512 == entry.sha512,
+                Err(e) => {
+                    warn!("Failed to read \"{}\": {}", object.display(), e);
+                    false
+                }
+            };
+
+            if !valid {
+                warn!("Cached file \"{}\" is corrupted, removing", entry.sha512);
+
+                self.remove(&entry).await?;
+                corrupted.push(entry);
+            }
+        }
+
+        Ok(corrupted)
+    }
+    /// Updates an index entry while holding its lock, so concurrent builds
+    /// don't overwrite each other's changes
+    ///
+    /// `entry` is only used if there's no entry on disk yet.
+    async fn update_entry<F: FnOnce(&mut CacheEntry)>(
+        &self,
+        entry: CacheEntry,
+        update: F,
+    ) -> Result<CacheEntry, CacheError> {
+        let path = self.entry_path(&entry.sha512);
+        let _lock = EntryLock::acquire(path.with_extension(CACHE_LOCK_EXTENSION)).await?;
+
+        let mut entry = read_entry(&path).await.unwrap_or(entry);
+        update(&mut entry);
+
+        self.write_entry(&entry).await?;
+
+        Ok(entry)
+    }
+    /// Writes an index entry, replacing it atomically
+    async fn write_entry(&self, entry: &CacheEntry) -> Result<(), CacheError> {
+        let path = self.entry_path(&entry.sha512);
+        let temp =
+            path.with_extension(format!("{}.{}", uuid::Uuid::new_v4(), CACHE_TEMP_EXTENSION));
+
+        let json = serde_jsonc::to_string_pretty(entry).map_err(CacheError::Json)?;
+
+        fs::write(&temp, json)
+            .await
+            .map_err(CacheError::Filesystem)?;
+        fs::rename(&temp, &path)
+            .await
+            .map_err(CacheError::Filesystem)
+    }
+}
+
+/// Lock file held while an index entry is updated, removed when dropped
+struct EntryLock(PathBuf);
+
+impl EntryLock {
+    async fn acquire(path: PathBuf) -> Result<Self, CacheError> {
+        let started = Instant::now();
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => return Ok(Self(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(CacheError::Filesystem(e)),
+            }
+
+            let stale = fs::metadata(&path)
+                .await
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.elapsed().ok())
+                .is_some_and(|age| age > LOCK_STALE_AFTER);
+
+            if stale || started.elapsed() > LOCK_STALE_AFTER * 2 {
+                warn!("Removing stale cache lock \"{}\"", path.display());
+
+                let _ = fs::remove_file(&path).await;
+            } else {
+                tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+impl Drop for EntryLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+async fn read_entry(path: &Path) -> Option<CacheEntry> {
+    let json = fs::read_to_string(path).await.ok()?;
+
+    serde_jsonc::from_str(&json).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Hashes a file in a single pass, returning its SHA-512, SHA-256 and SHA-1
+/// hashes and size
+async fn hash_file(path: PathBuf) -> std::io::Result<(String, String, String, u64)> {
+    spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut sha512 = Sha512::new();
+        let mut sha256 = Sha256::new();
+        let mut sha1 = Sha1::new();
+        let mut buffer = [0; FILE_BUFFER_SIZE];
+        let mut size = 0;
+
+        loop {
+            let bytes_read = file.read(&mut buffer)?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            sha512.update(&buffer[..bytes_read]);
+            sha256.update(&buffer[..bytes_read]);
+            sha1.update(&buffer[..bytes_read]);
+            size += bytes_read as u64;
+        }
+
+        Ok((
+            hex::encode(sha512.finalize()),
+            hex::encode(sha256.finalize()),
+            hex::encode(sha1.finalize()),
+            size,
+        ))
+    })
+    .await?
+}
+
+/// Parses a size in bytes with an optional binary unit (e.g. `512M`, `10G`)
+pub fn parse_size(size: &str) -> Result<u64, CacheError> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(CacheError::InvalidSize(size.to_string())),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| CacheError::InvalidSize(size.to_string()))
+}
+
+/// Formats a size in bytes with a binary unit
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("10GiB").unwrap(), 10 << 30);
+        assert_eq!(parse_size("2 kb").unwrap(), 2048);
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("10X").is_err());
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_gc() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let cache = Cache::new(root.join("cache"));
+
+        let file = root.join("download");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let object = cache
+            .insert(&file, "https://example.com/hello", root)
+            .await
+            .unwrap();
+
+        assert!(object.is_file());
+        assert!(!file.is_file());
+
+        // SHA-1 of "hello"
+        let entry = cache
+            .find("aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.urls, vec!["https://example.com/hello".to_string()]);
+
+        assert!(cache.verify().await.unwrap().is_empty());
+
+        let summary = cache.gc(Some(0), None).await.unwrap();
+        assert_eq!(summary.removed, 1);
+        assert_eq!(summary.freed, 5);
+        assert!(cache.entries().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_touch() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let cache = Cache::new(root.join("cache"));
+
+        let file = root.join("download");
+        std::fs::write(&file, b"hello").unwrap();
+        cache
+            .insert(&file, "https://example.com/0", root)
+            .await
+            .unwrap();
+
+        let entry = cache
+            .find("aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let touches = (1..=8).map(|i| {
+            let cache = cache.clone();
+            let entry = entry.clone();
+            let root = root.to_path_buf();
+
+            tokio::spawn(async move {
+                cache
+                    .touch(entry, &format!("https://example.com/{}", i), &root)
+                    .await
+                    .unwrap();
+            })
+        });
+
+        for touch in touches.collect::<Vec<_>>() {
+            touch.await.unwrap();
+        }
+
+        let entry = cache.find(&entry.sha512).await.unwrap().unwrap();
+        assert_eq!(entry.urls.len(), 9);
+        assert!(!cache
+            .entry_path(&entry.sha512)
+            .with_extension(CACHE_LOCK_EXTENSION)
+            .exists());
+    }
+}
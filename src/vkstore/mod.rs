@@ -2,6 +2,10 @@ use std::path;
 use tokio::{fs, task::spawn_blocking};
 use tracing::{debug, error};
 
+pub mod cache;
+
+pub use cache::Cache;
+
 const VKSTORE_PATH: &str = ".volkanic/";
 
 const VKSTORE_BUILD_SUFFIX: &str = "build/";
@@ -22,6 +26,8 @@ pub struct VolkanicStore {
     pub downloads_path: path::PathBuf,
     pub runtime_path: path::PathBuf,
     pub temp_path: path::PathBuf,
+    /// Download cache shared with other stores
+    pub cache_path: Option<path::PathBuf>,
 }
 
 async fn clear_dir<T: AsRef<path::Path>>(path: T) -> tokio::io::Result<()> {
@@ -83,6 +89,7 @@ impl VolkanicStore {
             downloads_path: path::PathBuf::from(VKSTORE_PATH).join(VKSTORE_DOWNLOADS_SUFFIX),
             runtime_path: path::PathBuf::from(VKSTORE_PATH).join(VKSTORE_RUNTIME_SUFFIX),
             temp_path: path::PathBuf::from(VKSTORE_PATH).join(VKSTORE_TEMP_SUFFIX),
+            cache_path: None,
         }
     }
     /// Creates a new `VolkanicStore` with a custom root directory
@@ -95,6 +102,7 @@ impl VolkanicStore {
             downloads_path: root.to_path_buf().clone().join(VKSTORE_DOWNLOADS_SUFFIX),
             runtime_path: root.to_path_buf().clone().join(VKSTORE_RUNTIME_SUFFIX),
             temp_path: root.to_path_buf().clone().join(VKSTORE_TEMP_SUFFIX),
+            cache_path: None,
         }
     }
     /// Changes the build directory for the store
@@ -113,6 +121,18 @@ impl VolkanicStore {
 
         store
     }
+    /// Uses a download cache shared with other stores
+    pub async fn override_cache<P: AsRef<path::Path>>(&self, path: P) -> Self {
+        let mut store = self.clone();
+
+        store.cache_path = Some(path.as_ref().to_path_buf());
+
+        store
+    }
+    /// Returns the shared download cache, if enabled
+    pub fn cache(&self) -> Option<Cache> {
+        self.cache_path.as_ref().map(Cache::new)
+    }
     /// Create directories for store
     pub async fn init(&self) -> Result<(), StoreError> {
         self.create().await?;